@@ -0,0 +1,184 @@
+use std::{collections::HashMap, fs::File, io::BufReader, rc::Rc};
+
+use monitor::cuda::{BBId, InstrId, KernelTy};
+use serde::{Deserialize, Serialize};
+
+use crate::dtest::{SiteKind, TestResult};
+
+pub const SAMPLING_FILE: &str = "sampling.json";
+
+/// Sequential sampling, runs are added in rounds until every tested site is decided
+#[derive(Debug, Clone, Copy)]
+pub struct Adaptive {
+    /// Runs added to each input class per round
+    pub step: usize,
+    /// Maximum runs of each input class
+    pub max: usize,
+    /// A p value in `[threshold / band, threshold * band)` is undecided, unless it is 1
+    pub band: f64,
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Self {
+            step: 2,
+            max: 20,
+            band: 2.0,
+        }
+    }
+}
+
+impl Adaptive {
+    /// A p value of 1 is never undecided, more runs can't lower it when the sides are the same,
+    /// even if the band around a threshold of 1 or more takes it in
+    pub fn is_undecided(&self, p: f64, threshold: f64) -> bool {
+        p >= threshold / self.band && p < threshold * self.band && p < 1.0
+    }
+}
+
+/// A site whose p value was still close to the threshold when sampling stopped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndecidedSite {
+    pub kernel: String,
+    pub ctx: String,
+    /// Control flow, accessed addresses, bank conflicts or transactions
+    #[serde(default)]
+    pub kind: SiteKind,
+    pub bb: BBId,
+    pub instr: Option<InstrId>,
+    /// p value after multiple-testing correction
+    pub p: f64,
+}
+
+/// Summary of an adaptive campaign, saved to `sampling.json` next to the traces
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sampling {
+    pub fix_runs: usize,
+    pub rnd_runs: usize,
+    pub max_runs: usize,
+    pub rounds: usize,
+    pub undecided: Vec<UndecidedSite>,
+}
+
+impl Sampling {
+    pub fn new(
+        adaptive: &Adaptive,
+        res: &TestResult,
+        kernels: &HashMap<KernelTy, Rc<String>>,
+        threshold: f64,
+    ) -> Self {
+        let undecided = res
+            .sites()
+            .filter(|site| adaptive.is_undecided(site.p_adjusted, threshold))
+            .map(|site| UndecidedSite {
+                kernel: kernels
+                    .get(&site.ty)
+                    .map(|name| name.to_string())
+                    .unwrap_or_default(),
+                ctx: site.ctx.to_string(),
+                kind: site.kind,
+                bb: site.bb,
+                instr: site.instr,
+                p: site.p_adjusted,
+            })
+            .collect();
+
+        Self {
+            fix_runs: 0,
+            rnd_runs: 0,
+            max_runs: adaptive.max,
+            rounds: 0,
+            undecided,
+        }
+    }
+
+    pub fn is_decided(&self) -> bool {
+        self.undecided.is_empty()
+    }
+
+    pub fn save(&self, root_path: &str) -> std::io::Result<()> {
+        let file = File::create(format!("{root_path}/{SAMPLING_FILE}"))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn load(root_path: &str) -> std::io::Result<Self> {
+        let reader = BufReader::new(File::open(format!("{root_path}/{SAMPLING_FILE}"))?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Adaptive, Sampling};
+    use crate::{
+        fixture::{Kernel, TempDir},
+        indexed_dirs, Analyzer,
+    };
+
+    #[test]
+    pub fn test_undecided_band() {
+        let adaptive = Adaptive {
+            band: 2.0,
+            ..Default::default()
+        };
+
+        assert!(!adaptive.is_undecided(0.01, 0.05));
+        assert!(adaptive.is_undecided(0.03, 0.05));
+        assert!(adaptive.is_undecided(0.09, 0.05));
+        assert!(!adaptive.is_undecided(0.1, 0.05));
+        assert!(!adaptive.is_undecided(1.0, 0.05));
+        // the default threshold of 2.0 has the band [1, 4)
+        assert!(!adaptive.is_undecided(1.0, 2.0));
+        assert!(!adaptive.is_undecided(0.5, 2.0));
+    }
+
+    #[test]
+    pub fn test_record_adaptive() {
+        let root = TempDir::new("adaptive");
+        let adaptive = Adaptive {
+            step: 2,
+            max: 8,
+            band: 2.0,
+        };
+        let analyzer = |fix: isize, rnd: isize, threshold, trace_path| Analyzer {
+            fix_cmd: Kernel::new().node(fix).stub(),
+            rnd_cmd: Kernel::new().node(rnd).stub(),
+            fix_times: 2,
+            rnd_times: 2,
+            threshold,
+            trace_path,
+            ..Default::default()
+        };
+
+        // identical inputs are decided in the first round
+        let sampling = analyzer(1, 1, 0.2, root.join("same"))
+            .record_adaptive(&adaptive)
+            .unwrap();
+        assert_eq!(
+            (sampling.rounds, sampling.fix_runs, sampling.rnd_runs),
+            (1, 2, 2)
+        );
+        assert!(Sampling::load(&root.join("same")).is_ok());
+
+        // with the default threshold the same sides stop at once instead of running to max
+        let sampling = analyzer(1, 1, 2.0, root.join("default"))
+            .record_adaptive(&adaptive)
+            .unwrap();
+        assert_eq!((sampling.rounds, sampling.fix_runs), (1, 2));
+
+        // different inputs need more runs before the p value leaves the band
+        let mut diff = analyzer(1, 2, 0.2, root.join("diff"));
+        let sampling = diff.record_adaptive(&adaptive).unwrap();
+        assert!(sampling.rounds > 1);
+        assert!(sampling.is_decided());
+        assert_eq!(sampling.fix_runs, sampling.rnd_runs);
+        assert_eq!(
+            indexed_dirs(&root.join("diff/fix")).len(),
+            sampling.fix_runs
+        );
+
+        let report = diff.analyze().unwrap();
+        assert_eq!(report.sampling.unwrap().fix_runs, sampling.fix_runs);
+    }
+}
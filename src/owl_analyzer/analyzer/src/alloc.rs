@@ -1,30 +1,241 @@
-use monitor::raw::RawAlloc;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    fmt::Display,
+    hash::{Hash, Hasher},
+};
 
-use crate::memory::TargetAddr;
+use monitor::raw::{RawAlloc, RawAllocContext};
+
+use crate::{memory::TargetAddr, trace::TraceCtx};
+
+/// Identity of a memory pool kept between runs, unlike its base address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize)]
+pub struct PoolId {
+    /// Position of the pool in the allocations live at the kernel launch
+    pub order: usize,
+    pub size: u64,
+    /// Hash of the host call stack of the allocation, 0 if not recorded
+    pub site: u64,
+}
+
+/// Host side record of a device allocation
+#[derive(Debug, Clone)]
+pub struct Allocation {
+    pub addr: u64,
+    pub size: u64,
+    /// Allocating function, e.g. `cudaMalloc`
+    pub func: String,
+    /// Host call stack, empty if not recorded
+    pub ctx: TraceCtx,
+}
+
+impl Allocation {
+    /// Attach to every allocation the call stack recorded for its address, the first one if
+    /// the address was allocated again
+    pub fn from_raw(alloc: Vec<RawAlloc>, context: Vec<RawAllocContext>) -> Vec<Self> {
+        let mut contexts = BTreeMap::new();
+        for c in context {
+            contexts
+                .entry(c.addr)
+                .or_insert_with(|| TraceCtx::from_raw(c.bt.into_iter()));
+        }
+
+        alloc
+            .into_iter()
+            .map(|a| Self {
+                addr: a.addr,
+                size: a.size,
+                func: a.name,
+                ctx: contexts.get(&a.addr).cloned().unwrap_or_else(TraceCtx::new),
+            })
+            .collect()
+    }
+}
+
+/// A memory pool as reported, named by its allocation if recorded
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Buffer {
+    pub order: usize,
+    pub size: u64,
+    /// Allocating function
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub func: Option<String>,
+    /// Source location of the allocation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+}
+
+impl Display for Buffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.site, &self.func) {
+            (Some(site), _) => write!(f, "buffer allocated at {site}"),
+            (None, Some(func)) if !func.is_empty() => write!(f, "buffer allocated by {func}"),
+            _ => write!(f, "buffer {}", self.order),
+        }?;
+        write!(f, " ({})", human_size(self.size))
+    }
+}
+
+/// `bytes` in the largest binary unit dividing it
+fn human_size(bytes: u64) -> String {
+    const MIB: u64 = 1 << 20;
+    const KIB: u64 = 1 << 10;
+    if bytes >= MIB && bytes.is_multiple_of(MIB) {
+        format!("{} MiB", bytes / MIB)
+    } else if bytes >= KIB && bytes.is_multiple_of(KIB) {
+        format!("{} KiB", bytes / KIB)
+    } else {
+        format!("{bytes} B")
+    }
+}
 
 #[derive(Debug)]
 pub struct MemPool {
+    pub id: PoolId,
     pub start: u64,
     pub size: u64,
+    /// Host side record of the allocation
+    pub alloc: Option<Allocation>,
 }
 
-impl From<RawAlloc> for MemPool {
-    fn from(value: RawAlloc) -> Self {
+impl MemPool {
+    /// The `order`-th allocation live at a kernel launch
+    pub fn new(order: usize, value: &RawAlloc) -> Self {
         Self {
-            start: value.addr as u64,
-            size: value.size as u64,
+            id: PoolId {
+                order,
+                size: value.size,
+                site: 0,
+            },
+            start: value.addr,
+            size: value.size,
+            alloc: None,
+        }
+    }
+
+    /// Attach the allocation of the pool among `allocs`, its call stack joins the identity
+    pub fn alloc(mut self, allocs: &[Allocation]) -> Self {
+        self.alloc = allocs.iter().find(|a| a.addr == self.start).cloned();
+        if let Some(alloc) = self.alloc.as_ref().filter(|a| !a.ctx.is_empty()) {
+            let mut hasher = DefaultHasher::new();
+            alloc.ctx.to_string().hash(&mut hasher);
+            self.id.site = hasher.finish();
+        }
+        self
+    }
+
+    pub fn buffer(&self) -> Buffer {
+        Buffer {
+            order: self.id.order,
+            size: self.size,
+            func: self.alloc.as_ref().map(|a| a.func.clone()),
+            site: self.alloc.as_ref().and_then(|a| a.ctx.location()),
         }
     }
-}
 
-impl MemPool {
     pub fn convert(&self, mut addr: TargetAddr) -> Option<TargetAddr> {
         if addr.offset >= self.start && addr.offset < self.start + self.size {
             addr.offset = addr.offset - self.start;
-            addr.pool = Some(self.start);
+            addr.pool = Some(self.id);
             return Some(addr);
         } else {
             return None;
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use monitor::{
+        cuda::MemType,
+        raw::{RawAlloc, RawAllocContext, RawCsFrame},
+    };
+
+    use super::{Allocation, MemPool};
+    use crate::memory::TargetAddr;
+
+    #[test]
+    pub fn test_pool_identity() {
+        // the same two buffers allocated at different addresses in two runs
+        let pools = |starts: [u64; 2]| -> Vec<MemPool> {
+            starts
+                .iter()
+                .enumerate()
+                .map(|(order, addr)| {
+                    MemPool::new(
+                        order,
+                        &RawAlloc {
+                            addr: *addr,
+                            name: String::new(),
+                            size: 256,
+                        },
+                    )
+                })
+                .collect()
+        };
+        let convert = |pools: &[MemPool], addr| {
+            pools
+                .iter()
+                .find_map(|p| p.convert(TargetAddr::new(addr, None, MemType::Global)))
+                .unwrap()
+        };
+        let (l, r) = (pools([0x1000, 0x2000]), pools([0x8000, 0x7000]));
+
+        assert_eq!(convert(&l, 0x1010), convert(&r, 0x8010));
+        assert_eq!(convert(&l, 0x2010), convert(&r, 0x7010));
+        // the same offset of different buffers
+        assert_ne!(convert(&l, 0x1010), convert(&l, 0x2010));
+        assert_eq!(convert(&r, 0x7010).pool.unwrap().order, 1);
+    }
+
+    #[test]
+    pub fn test_buffer() {
+        let raw = |addr, size| RawAlloc {
+            addr,
+            name: "cudaMalloc".to_owned(),
+            size,
+        };
+        let frame = |func: &str, file: &str, offset| RawCsFrame {
+            addr: 0x400000,
+            func: func.to_owned(),
+            file: file.to_owned(),
+            offset,
+        };
+        let context = |addr, bt| RawAllocContext { addr, bt };
+        // contexts are matched by address, not by their order
+        let allocs = Allocation::from_raw(
+            vec![raw(0x1000, 4096), raw(0x3000, 100)],
+            vec![
+                context(0x3000, vec![frame("cudaMalloc", "", 0)]),
+                context(
+                    0x1000,
+                    vec![frame("cudaMalloc", "", 0), frame("main", "aes.cu", 120)],
+                ),
+            ],
+        );
+
+        let pool = MemPool::new(0, &raw(0x1000, 4096)).alloc(&allocs);
+        assert_eq!(
+            pool.buffer().to_string(),
+            "buffer allocated at aes.cu:120 (4 KiB)"
+        );
+        assert_ne!(pool.id.site, 0);
+
+        // no source location, or no allocation record
+        let pool = MemPool::new(1, &raw(0x3000, 100)).alloc(&allocs);
+        assert_eq!(
+            pool.buffer().to_string(),
+            "buffer allocated by cudaMalloc (100 B)"
+        );
+        let pool = MemPool::new(2, &raw(0x8000, 3 << 20)).alloc(&allocs);
+        assert_eq!(pool.buffer().to_string(), "buffer 2 (3 MiB)");
+        assert_eq!(pool.id.site, 0);
+
+        // contexts of other addresses are dropped
+        let allocs = Allocation::from_raw(
+            vec![raw(0x1000, 4096)],
+            vec![context(0x2000, vec![frame("main", "aes.cu", 120)])],
+        );
+        assert!(allocs[0].ctx.is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+use std::{collections::HashSet, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::dtest::{SiteKey, SiteKind, TestResult};
+
+/// Level of the baseline when the threshold reports every site
+const DEFAULT_LEVEL: f64 = 0.05;
+
+/// How sites that differ between runs of the same fixed input are reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Calibration {
+    /// No fixed versus fixed baseline
+    #[default]
+    None,
+    /// Report them as nondeterministic instead of leaking
+    Mark,
+    /// Leave them out of the report
+    Suppress,
+}
+
+impl FromStr for Calibration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "mark" => Ok(Self::Mark),
+            "suppress" => Ok(Self::Suppress),
+            _ => Err(format!(
+                "unknown calibration `{s}`, expect none, mark or suppress"
+            )),
+        }
+    }
+}
+
+/// Sites and kernel calls differing under identical inputs
+#[derive(Debug, Default)]
+pub struct Baseline {
+    sites: HashSet<SiteKey>,
+    /// Contexts of kernel calls launched a different number of times
+    ctxs: HashSet<String>,
+}
+
+impl Baseline {
+    /// Collect what `res`, a test of fixed input runs against each other, finds below
+    /// `threshold`, or `DEFAULT_LEVEL` if the threshold is no significance level.
+    /// Nodes executed by one half only always differ
+    pub fn new(res: &TestResult, threshold: f64) -> Self {
+        let level = if threshold < 1.0 {
+            threshold
+        } else {
+            DEFAULT_LEVEL
+        };
+        let mut sites: HashSet<_> = res
+            .sites()
+            .filter(|site| site.p_adjusted < level)
+            .map(|site| site.key())
+            .collect();
+        sites.extend(res.eq_kernel.iter().flat_map(|k| {
+            k.cf.iter()
+                .filter(|cf| cf.unpaired)
+                .map(|cf| (k.ctx.to_string(), k.ty, SiteKind::Cf, cf.id, None))
+        }));
+
+        Self {
+            sites,
+            ctxs: res.diff_kernel.iter().map(|k| k.ctx.to_string()).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.sites.len() + self.ctxs.len()
+    }
+
+    pub fn contains(&self, key: &SiteKey) -> bool {
+        self.ctxs.contains(&key.0) || self.sites.contains(key)
+    }
+
+    /// Mark every site of `res` in the baseline as nondeterministic, or drop it
+    pub fn apply(&self, res: &mut TestResult, calibration: Calibration) {
+        let suppress = calibration == Calibration::Suppress;
+
+        for k in res.eq_kernel.iter_mut() {
+            let ctx = k.ctx.to_string();
+            for cf in k.cf.iter_mut() {
+                cf.nondeterministic =
+                    self.contains(&(ctx.clone(), k.ty, SiteKind::Cf, cf.id, None));
+            }
+            for df in k.df.iter_mut() {
+                df.nondeterministic =
+                    self.contains(&(ctx.clone(), k.ty, SiteKind::Df, df.id, Some(df.instr)));
+            }
+            let ty = k.ty;
+            for (kind, cost) in k.costs_mut() {
+                cost.nondeterministic =
+                    self.contains(&(ctx.clone(), ty, kind, cost.id, Some(cost.instr)));
+            }
+            if suppress {
+                k.cf.retain(|cf| !cf.nondeterministic);
+                k.df.retain(|df| !df.nondeterministic);
+                k.bank.retain(|bank| !bank.nondeterministic);
+                k.coalescing.retain(|cost| !cost.nondeterministic);
+            }
+        }
+
+        if suppress {
+            res.diff_kernel
+                .retain(|k| !self.ctxs.contains(&k.ctx.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Baseline, Calibration};
+    use crate::dtest::{EqKernelResult, NodeCfResult, TestResult};
+
+    /// Test result of one kernel call with a node per p value, unpaired if it is None
+    fn result(p: &[Option<f64>]) -> TestResult {
+        let mut kernel = EqKernelResult::new().ty(1);
+        for (id, p) in (0..).zip(p) {
+            kernel.push_cf(match p {
+                Some(p) => NodeCfResult::new(id).p_value(*p),
+                None => NodeCfResult::new(id).unpaired(),
+            });
+        }
+        let mut res = TestResult::new();
+        res.push_eq(kernel);
+        res
+    }
+
+    #[test]
+    pub fn test_baseline() {
+        // fixed input runs against each other, node 1 is stable and node 2 runs in one half only
+        let fixed = result(&[Some(0.01), Some(0.5), None]);
+        let baseline = Baseline::new(&fixed, 0.05);
+        assert_eq!(baseline.len(), 2);
+        assert_eq!(Baseline::new(&fixed, 0.001).len(), 1);
+        // the threshold reports every site, the baseline keeps the default level
+        assert_eq!(Baseline::new(&fixed, 2.0).len(), 2);
+
+        let tested = || result(&[Some(0.001); 3]);
+        let nondeterministic = |res: &TestResult| -> Vec<_> {
+            res.eq_kernel[0]
+                .cf
+                .iter()
+                .map(|cf| (cf.id, cf.nondeterministic))
+                .collect()
+        };
+
+        let mut res = tested();
+        baseline.apply(&mut res, Calibration::Mark);
+        assert_eq!(
+            nondeterministic(&res),
+            vec![(0, true), (1, false), (2, true)]
+        );
+
+        let mut res = tested();
+        baseline.apply(&mut res, Calibration::Suppress);
+        assert_eq!(nondeterministic(&res), vec![(1, false)]);
+
+        assert_eq!("mark".parse(), Ok(Calibration::Mark));
+    }
+}
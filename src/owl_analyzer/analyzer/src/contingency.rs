@@ -0,0 +1,332 @@
+use crate::{
+    hist::{chi2_p_value, ln_gamma},
+    observation::Observations,
+};
+
+/// Smallest expected count the chi-squared approximation is trusted for
+const MIN_EXPECTED: f64 = 5.0;
+
+/// Most tables enumerated by the Fisher exact test
+const FISHER_TABLES: usize = 100_000;
+
+/// 2 x k contingency table, the fixed and random side counts of the same k categories
+pub struct Contingency<'a> {
+    l: &'a [f64],
+    r: &'a [f64],
+}
+
+impl<'a> Contingency<'a> {
+    /// Categories missing from the end of the shorter side have a zero count there
+    pub fn new(l: &'a [f64], r: &'a [f64]) -> Self {
+        Self { l, r }
+    }
+
+    /// Counts of both sides in every category
+    fn pairs(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        let count = |side: &[f64], idx| side.get(idx).copied().unwrap_or_default();
+        (0..self.l.len().max(self.r.len())).map(move |idx| (count(self.l, idx), count(self.r, idx)))
+    }
+
+    fn totals(&self) -> (f64, f64) {
+        (self.l.iter().sum(), self.r.iter().sum())
+    }
+
+    /// Observed and expected counts of both rows of every non-empty column
+    fn columns(&self) -> impl Iterator<Item = [(f64, f64); 2]> + '_ {
+        let (l_sum, r_sum) = self.totals();
+        let total = l_sum + r_sum;
+
+        self.pairs()
+            .filter(|(lc, rc)| lc + rc > 0.0)
+            .map(move |(lc, rc)| {
+                let col = lc + rc;
+                [(lc, l_sum * col / total), (rc, r_sum * col / total)]
+            })
+    }
+
+    /// Sum `cell(observed, expected)` over every cell, returns the statistic and its degrees
+    /// of freedom, columns empty on both sides are dropped
+    pub fn statistic(&self, cell: impl Fn(f64, f64) -> f64) -> (f64, usize) {
+        let mut stat = 0.0;
+        let mut cols = 0;
+        for [(lo, le), (ro, re)] in self.columns() {
+            cols += 1;
+            stat += cell(lo, le) + cell(ro, re);
+        }
+
+        (stat, cols.max(1) - 1)
+    }
+
+    /// Pearson chi-squared contribution of every column, empty columns contribute zero
+    pub fn contributions(&self) -> Vec<f64> {
+        let (l_sum, r_sum) = self.totals();
+        let total = l_sum + r_sum;
+
+        self.pairs()
+            .map(|(lc, rc)| {
+                let col = lc + rc;
+                if col == 0.0 || l_sum == 0.0 || r_sum == 0.0 {
+                    return 0.0;
+                }
+                let (le, re) = (l_sum * col / total, r_sum * col / total);
+                (lc - le).powi(2) / le + (rc - re).powi(2) / re
+            })
+            .collect()
+    }
+
+    /// Cramér's V of the class and the category, zero if a row or all but one column is empty
+    pub fn cramers_v(&self) -> f64 {
+        let (l_sum, r_sum) = self.totals();
+        let (stat, df) = self.statistic(|o, e| (o - e).powi(2) / e);
+        if l_sum == 0.0 || r_sum == 0.0 || df == 0 {
+            return 0.0;
+        }
+        // min(rows, columns) - 1 is one for two rows
+        (stat / (l_sum + r_sum)).sqrt().min(1.0)
+    }
+
+    /// Mutual information in bits between the input class and the observed category, the
+    /// class is weighted by its observations.
+    /// The plug-in estimate is bias corrected by Miller-Madow and kept within `[0, H(class)]`
+    pub fn mutual_information(&self) -> f64 {
+        let (l_sum, r_sum) = self.totals();
+        let total = l_sum + r_sum;
+        if l_sum == 0.0 || r_sum == 0.0 {
+            return 0.0;
+        }
+
+        let mut mi = 0.0;
+        let (mut cols, mut cells) = (0, 0);
+        for [(lo, le), (ro, re)] in self.columns() {
+            cols += 1;
+            for (o, e) in [(lo, le), (ro, re)] {
+                if o > 0.0 {
+                    cells += 1;
+                    // o / N * log(p(x, y) / (p(x) p(y))), and e = N p(x) p(y)
+                    mi += o / total * (o / e).log2();
+                }
+            }
+        }
+
+        // H(class) + H(category) - H(class, category), each biased by (K - 1) / 2N
+        let bias = (1 + (cols - 1) - (cells - 1)) as f64 / (2.0 * total * std::f64::consts::LN_2);
+        let class_entropy: f64 = [l_sum, r_sum]
+            .iter()
+            .map(|s| -s / total * (s / total).log2())
+            .sum();
+
+        (mi + bias).clamp(0.0, class_entropy)
+    }
+
+    /// p value of both rows following the same distribution, the Fisher exact test is used
+    /// when an expected count is small and the tables can be enumerated, else chi-squared
+    pub fn p_value(&self) -> f64 {
+        let small = self
+            .columns()
+            .any(|[(_, le), (_, re)]| le < MIN_EXPECTED || re < MIN_EXPECTED);
+        if small {
+            if let Some(p) = self.fisher() {
+                return p;
+            }
+        }
+
+        let (stat, df) = self.statistic(|o, e| (o - e).powi(2) / e);
+        if df == 0 {
+            return 1.0;
+        }
+        chi2_p_value(stat, df as f64)
+    }
+
+    /// Fisher exact test of the Freeman-Halton extension, the probability of every table with
+    /// the same margins and at most the observed probability.
+    /// None if there are more than `FISHER_TABLES` tables
+    pub fn fisher(&self) -> Option<f64> {
+        let cols: Vec<(usize, usize)> = self
+            .pairs()
+            .filter(|(lc, rc)| lc + rc > 0.0)
+            .map(|(lc, rc)| (lc.round() as usize, (lc + rc).round() as usize))
+            .collect();
+        let row: usize = cols.iter().map(|(x, _)| x).sum();
+        let total: usize = cols.iter().map(|(_, c)| c).sum();
+
+        let ln_margin = ln_choose(total, row);
+        let observed: f64 = cols.iter().map(|(x, c)| ln_choose(*c, *x)).sum::<f64>();
+
+        // capacity of the columns after each one, prunes first rows that can not be completed
+        let mut rest = vec![0; cols.len() + 1];
+        for i in (0..cols.len()).rev() {
+            rest[i] = rest[i + 1] + cols[i].1;
+        }
+
+        // every visited table can be completed, stop as soon as `visit` returns false
+        fn walk(
+            cols: &[(usize, usize)],
+            rest: &[usize],
+            idx: usize,
+            left: usize,
+            ln_p: f64,
+            visit: &mut impl FnMut(f64) -> bool,
+        ) -> bool {
+            if idx == cols.len() {
+                return visit(ln_p);
+            }
+            let c = cols[idx].1;
+            let lo = left.saturating_sub(rest[idx + 1]);
+            (lo..=c.min(left))
+                .all(|x| walk(cols, rest, idx + 1, left - x, ln_p + ln_choose(c, x), visit))
+        }
+
+        // count the tables before summing, no more than one past the limit
+        let mut tables = 0;
+        walk(&cols, &rest, 0, row, 0.0, &mut |_| {
+            tables += 1;
+            tables <= FISHER_TABLES
+        });
+        if tables > FISHER_TABLES {
+            return None;
+        }
+
+        let mut p = 0.0;
+        walk(&cols, &rest, 0, row, 0.0, &mut |ln_p| {
+            // tolerate rounding of tables as likely as the observed one
+            if ln_p <= observed + 1e-7 {
+                p += (ln_p - ln_margin).exp();
+            }
+            true
+        });
+
+        Some(p.clamp(0.0, 1.0))
+    }
+}
+
+/// Bits a site leaks about the input class, the larger estimate of its pooled counts and of
+/// whether it is reached in every run
+pub fn leaked_bits(lh: &[f64], rh: &[f64], l_runs: &Observations, r_runs: &Observations) -> f64 {
+    let counts = Contingency::new(lh, rh).mutual_information();
+
+    let presence = |runs: &Observations| {
+        let reached = runs.reached() as f64;
+        [reached, runs.len() as f64 - reached]
+    };
+    let (lp, rp) = (presence(l_runs), presence(r_runs));
+    let reached = Contingency::new(&lp, &rp).mutual_information();
+
+    counts.max(reached)
+}
+
+fn ln_choose(n: usize, k: usize) -> f64 {
+    ln_gamma(n as f64 + 1.0) - ln_gamma(k as f64 + 1.0) - ln_gamma((n - k) as f64 + 1.0)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::observation::Observations;
+
+    use super::{leaked_bits, Contingency};
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    pub fn test_fisher() {
+        let fisher = |l: &[f64], r: &[f64]| Contingency::new(l, r).fisher().unwrap();
+
+        assert_close(fisher(&[1.0, 9.0], &[11.0, 3.0]), 0.002759456185220084);
+        assert_close(fisher(&[3.0, 1.0], &[1.0, 3.0]), 0.4857142857142855);
+        // Freeman-Halton 2 x k
+        assert_close(
+            fisher(&[2.0, 0.0, 3.0], &[0.0, 4.0, 1.0]),
+            0.07936507936507937,
+        );
+        assert_close(
+            fisher(&[1.0, 2.0, 0.0, 0.0, 4.0], &[3.0, 0.0, 0.0, 2.0, 1.0]),
+            0.12004662004662005,
+        );
+        assert_close(fisher(&[2.0, 2.0], &[2.0, 2.0]), 1.0);
+
+        // few tables of large counts, two of them here
+        assert!((fisher(&[1e6, 1.0], &[1e6, 0.0]) - 1.0).abs() < 1e-6);
+
+        // too many tables
+        assert!(Contingency::new(&[500.0; 8], &[500.0; 8])
+            .fisher()
+            .is_none());
+        assert!(Contingency::new(&[1e6, 1e6, 1.0], &[1e6, 1e6, 0.0])
+            .fisher()
+            .is_none());
+    }
+
+    #[test]
+    pub fn test_contingency() {
+        // small counts go to the exact test
+        let small = Contingency::new(&[3.0, 1.0], &[1.0, 3.0]);
+        assert_close(small.p_value(), 0.4857142857142855);
+
+        // large counts use chi-squared
+        let large = Contingency::new(&[10.0, 20.0], &[20.0, 10.0]);
+        assert_close(large.p_value(), 0.009823274507519245);
+        assert_eq!(large.statistic(|o, e| (o - e).powi(2) / e).1, 1);
+
+        let contributions =
+            Contingency::new(&[10.0, 0.0, 20.0], &[10.0, 0.0, 40.0]).contributions();
+        assert_eq!(contributions[1], 0.0);
+        assert!(contributions[0] > contributions[2]);
+        let (stat, _) = Contingency::new(&[10.0, 0.0, 20.0], &[10.0, 0.0, 40.0])
+            .statistic(|o, e| (o - e).powi(2) / e);
+        assert_close(contributions.iter().sum::<f64>(), stat);
+
+        // chi-squared 6.667 of 60 observations
+        assert_close(large.cramers_v(), 1.0 / 3.0);
+        assert_close(
+            Contingency::new(&[40.0, 0.0], &[0.0, 40.0]).cramers_v(),
+            1.0,
+        );
+        assert_eq!(Contingency::new(&[4.0], &[7.0]).cramers_v(), 0.0);
+        // a category missing from one side
+        assert_eq!(
+            Contingency::new(&[40.0], &[0.0, 40.0]).cramers_v(),
+            Contingency::new(&[40.0, 0.0], &[0.0, 40.0]).cramers_v()
+        );
+    }
+
+    #[test]
+    pub fn test_mutual_information() {
+        let mi = |l: &[f64], r: &[f64]| Contingency::new(l, r).mutual_information();
+
+        // plug-in 0.0817 bits, less the bias of one degree of freedom
+        assert_close(mi(&[10.0, 20.0], &[20.0, 10.0]), 0.06968170727143569);
+        // the class is fully revealed, at most one bit
+        assert_close(mi(&[40.0, 0.0], &[0.0, 40.0]), 1.0);
+        // same distribution, the bias is not reported as leakage
+        assert_eq!(mi(&[5.0, 5.0], &[5.0, 5.0]), 0.0);
+        assert_eq!(mi(&[0.0, 0.0], &[3.0, 1.0]), 0.0);
+
+        // reached in every fixed run only
+        let runs = |reached: usize, num: usize| {
+            let mut obs = Observations::default();
+            (0..reached).for_each(|_| obs.merge(Observations::single(4.0)));
+            obs.padded(num)
+        };
+        assert_close(
+            leaked_bits(&[80.0], &[0.0], &runs(20, 20), &runs(0, 20)),
+            1.0,
+        );
+        // reached in half of the fixed runs, pooled counts of one category tell nothing
+        assert_close(
+            leaked_bits(&[40.0], &[0.0], &runs(10, 20), &runs(0, 20)),
+            0.31127812445913283,
+        );
+        assert_eq!(leaked_bits(&[4.0], &[4.0], &runs(2, 2), &runs(2, 2)), 0.0);
+
+        // 2 and 5 runs taking different branches reveal the class, all of its 0.86 bits
+        let class: f64 = [2.0f64 / 7.0, 5.0 / 7.0]
+            .iter()
+            .map(|p| -p * p.log2())
+            .sum();
+        assert_close(
+            leaked_bits(&[8.0, 0.0], &[0.0, 20.0], &runs(2, 2), &runs(5, 5)),
+            class,
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Multiple-testing correction applied over every tested site of a campaign
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Correction {
+    /// Raw p values
+    #[default]
+    None,
+    /// Family-wise error rate, `p * k`
+    Bonferroni,
+    /// Family-wise error rate, step-down Bonferroni
+    Holm,
+    /// False discovery rate of Benjamini-Hochberg
+    Bh,
+}
+
+impl FromStr for Correction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "bonferroni" => Ok(Self::Bonferroni),
+            "holm" => Ok(Self::Holm),
+            "bh" => Ok(Self::Bh),
+            _ => Err(format!(
+                "unknown correction `{s}`, expect none, bonferroni, holm or bh"
+            )),
+        }
+    }
+}
+
+impl Correction {
+    /// Adjusted p values of the family `p`, in the same order
+    pub fn adjust(&self, p: &[f64]) -> Vec<f64> {
+        let k = p.len() as f64;
+
+        // index of p values in ascending order
+        let mut order: Vec<_> = (0..p.len()).collect();
+        order.sort_by(|&a, &b| p[a].total_cmp(&p[b]));
+
+        let mut adjusted = p.to_vec();
+        match self {
+            Correction::None => {}
+            Correction::Bonferroni => {
+                adjusted.iter_mut().for_each(|p| *p *= k);
+            }
+            Correction::Holm => {
+                let mut max = 0f64;
+                for (rank, &idx) in order.iter().enumerate() {
+                    max = max.max(p[idx] * (k - rank as f64));
+                    adjusted[idx] = max;
+                }
+            }
+            Correction::Bh => {
+                let mut min = 1f64;
+                for (rank, &idx) in order.iter().enumerate().rev() {
+                    min = min.min(p[idx] * k / (rank + 1) as f64);
+                    adjusted[idx] = min;
+                }
+            }
+        }
+
+        adjusted.iter_mut().for_each(|p| *p = p.min(1.0));
+        adjusted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Correction;
+    use crate::dtest::{EqKernelResult, NodeCfResult, TestResult};
+
+    fn assert_all_close(a: &[f64], b: &[f64]) {
+        assert_eq!(a.len(), b.len());
+        for (a, b) in a.iter().zip(b.iter()) {
+            assert!((a - b).abs() < 1e-12, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    pub fn test_correction() {
+        let p = [0.04, 0.001, 0.03, 0.5];
+
+        assert_all_close(&Correction::None.adjust(&p), &p);
+        assert_all_close(
+            &Correction::Bonferroni.adjust(&p),
+            &[0.16, 0.004, 0.12, 1.0],
+        );
+        assert_all_close(&Correction::Holm.adjust(&p), &[0.09, 0.004, 0.09, 0.5]);
+        assert_all_close(
+            &Correction::Bh.adjust(&p),
+            &[0.04 * 4.0 / 3.0, 0.004, 0.04 * 4.0 / 3.0, 0.5],
+        );
+
+        assert!(Correction::Holm.adjust(&[]).is_empty());
+        assert_eq!("bh".parse::<Correction>(), Ok(Correction::Bh));
+    }
+
+    #[test]
+    pub fn test_correct_result() {
+        let mut res = TestResult::new();
+        let mut kernel = EqKernelResult::new().ty(1);
+        kernel.push_cf(NodeCfResult::new(0).p_value(0.1));
+        kernel.push_cf(NodeCfResult::new(1).p_value(0.01).unpaired());
+        kernel.push_cf(NodeCfResult::new(2).p_value(0.3));
+        res.push_eq(kernel);
+
+        // unpaired nodes aren't tested, two sites remain in the family
+        res.correct(Correction::Bonferroni);
+        let adjusted: Vec<_> = res.eq_kernel[0].cf.iter().map(|cf| cf.p_adjusted).collect();
+        assert_all_close(&adjusted, &[0.2, 0.01, 0.6]);
+
+        res.correct(Correction::None);
+        let adjusted: Vec<_> = res.eq_kernel[0].cf.iter().map(|cf| cf.p_adjusted).collect();
+        assert_all_close(&adjusted, &[0.1, 0.01, 0.3]);
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    contingency::leaked_bits,
+    granularity::{BANKS, BANK_WIDTH, SECTOR},
+    ltest::{Effect, LeakageTest, Sample},
+    memory::InstrOutcome,
+    observation::Observations,
+};
+
+/// Replays of a shared memory warp access to `addrs`, words in the same bank are served one
+/// after another, threads reading the same word are served at once
+pub fn bank_replays(addrs: impl IntoIterator<Item = u64>) -> usize {
+    let words: BTreeSet<_> = addrs.into_iter().map(|addr| addr / BANK_WIDTH).collect();
+    let mut banks: BTreeMap<u64, usize> = BTreeMap::new();
+    for word in words {
+        *banks.entry(word % BANKS).or_default() += 1;
+    }
+
+    banks.into_values().max().unwrap_or(1) - 1
+}
+
+/// Coalescing of global memory accesses, a warp access needs one transaction for every sector
+/// it touches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Coalescing {
+    /// Bytes of a sector
+    pub sector: u64,
+}
+
+impl Default for Coalescing {
+    fn default() -> Self {
+        Self { sector: SECTOR }
+    }
+}
+
+impl Coalescing {
+    /// Transactions of a global memory access to `addrs`
+    pub fn transactions(&self, addrs: impl IntoIterator<Item = u64>) -> usize {
+        let sector = self.sector.max(1);
+        addrs
+            .into_iter()
+            .map(|addr| addr / sector)
+            .collect::<BTreeSet<_>>()
+            .len()
+    }
+}
+
+/// Cost of the warp accesses of an instruction, bank conflict replays or memory transactions,
+/// in every access position. Every warp access recorded by the monitor is costed on its own
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WarpCost {
+    /// Warp accesses of every cost in every access position
+    pub data: Vec<BTreeMap<usize, usize>>,
+    /// Summed cost of every position in every merged run
+    pub runs: Observations,
+}
+
+impl WarpCost {
+    /// Record `warps` warp accesses of position `pos` of the current run costing `cost` each
+    pub fn add(&mut self, pos: usize, cost: usize, warps: usize) {
+        if self.data.len() <= pos {
+            self.data.resize(pos + 1, BTreeMap::new());
+        }
+        *self.data[pos].entry(cost).or_default() += warps;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.iter().all(|costs| costs.is_empty())
+    }
+
+    /// Record the current costs as the observation of a single run
+    pub fn observe_run(&mut self) {
+        let sum: usize = self
+            .data
+            .iter()
+            .flat_map(|costs| costs.iter().map(|(cost, runs)| cost * runs))
+            .sum();
+        self.runs = Observations::single(sum as f64);
+    }
+
+    pub fn merge(&mut self, other: Self) {
+        if self.data.len() < other.data.len() {
+            self.data.resize(other.data.len(), BTreeMap::new());
+        }
+        for (l, r) in self.data.iter_mut().zip(other.data) {
+            for (cost, runs) in r {
+                *l.entry(cost).or_default() += runs;
+            }
+        }
+        self.runs.merge(other.runs);
+    }
+
+    /// Test the cost distribution of every position, per-run observations are padded to the
+    /// `m` and `n` runs of the two sides
+    ///
+    /// Return None if neither side has a cost
+    pub fn test(
+        &self,
+        other: &Self,
+        m: usize,
+        n: usize,
+        test: &dyn LeakageTest,
+    ) -> Option<InstrOutcome> {
+        if self.is_empty() && other.is_empty() {
+            return None;
+        }
+
+        let (l_runs, r_runs) = (self.runs.padded(m), other.runs.padded(n));
+        let mut outcome = InstrOutcome {
+            p: 1.0,
+            effect: Effect::default(),
+            bits: 0.0,
+            positions: Vec::new(),
+            untestable: 0,
+            addr: None,
+        };
+        for (l, r) in self.data.iter().zip(other.data.iter()) {
+            if l.is_empty() || r.is_empty() {
+                outcome.untestable += usize::from(!l.is_empty() || !r.is_empty());
+                continue;
+            }
+
+            let (lh, rh) = aligned_costs(l, r);
+            let (l, r) = (
+                Sample {
+                    hist: &lh,
+                    runs: &l_runs,
+                },
+                Sample {
+                    hist: &rh,
+                    runs: &r_runs,
+                },
+            );
+            let tested = test.test(&l, &r);
+            if tested.p < outcome.p || outcome.positions.is_empty() {
+                outcome.p = tested.p;
+                outcome.effect = Effect::new(&l, &r, tested.statistic);
+            }
+            outcome.bits = outcome.bits.max(leaked_bits(&lh, &rh, &l_runs, &r_runs));
+            outcome.positions.push(tested.p);
+        }
+        let longer = if self.data.len() > other.data.len() {
+            &self.data
+        } else {
+            &other.data
+        };
+        outcome.untestable += longer
+            .iter()
+            .skip(self.data.len().min(other.data.len()))
+            .filter(|costs| !costs.is_empty())
+            .count();
+
+        Some(outcome)
+    }
+}
+
+/// Runs of both sides over every cost of either, in cost order
+fn aligned_costs(l: &BTreeMap<usize, usize>, r: &BTreeMap<usize, usize>) -> (Vec<f64>, Vec<f64>) {
+    let costs: BTreeSet<_> = l.keys().chain(r.keys()).collect();
+    costs
+        .into_iter()
+        .map(|cost| {
+            (
+                l.get(cost).copied().unwrap_or_default() as f64,
+                r.get(cost).copied().unwrap_or_default() as f64,
+            )
+        })
+        .unzip()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bank_replays, Coalescing, WarpCost};
+    use crate::ltest::TestMethod;
+
+    #[test]
+    pub fn test_bank_replays() {
+        // consecutive words are in distinct banks
+        assert_eq!(bank_replays((0..32).map(|i| i * 4)), 0);
+        // a broadcast of one word
+        assert_eq!(bank_replays([8, 8, 9, 10]), 0);
+        // a stride of two words puts two of them in every bank
+        assert_eq!(bank_replays((0..32).map(|i| i * 8)), 1);
+        // every word in bank 0
+        assert_eq!(bank_replays((0..32).map(|i| i * 128)), 31);
+        assert_eq!(bank_replays([]), 0);
+    }
+
+    #[test]
+    pub fn test_transactions() {
+        let coalescing = Coalescing::default();
+        // 32 consecutive words are 4 sectors
+        assert_eq!(coalescing.transactions((0..32).map(|i| 0x1000 + i * 4)), 4);
+        // the same line in a different order, or partly
+        assert_eq!(coalescing.transactions([0x1060, 0x1000, 0x1020, 0x1040]), 4);
+        assert_eq!(coalescing.transactions([0x1000, 0x1004]), 1);
+        // a stride of a sector
+        assert_eq!(coalescing.transactions((0..32).map(|i| i * 32)), 32);
+
+        let coalescing = Coalescing { sector: 128 };
+        assert_eq!(coalescing.transactions((0..32).map(|i| 0x1000 + i * 4)), 1);
+    }
+
+    #[test]
+    pub fn test_warp_cost() {
+        let runs = |costs: &[usize]| {
+            let mut merged = WarpCost::default();
+            for cost in costs {
+                let mut run = WarpCost::default();
+                run.add(0, *cost, 1);
+                run.observe_run();
+                merged.merge(run);
+            }
+            merged
+        };
+
+        let fix = runs(&[0; 20]);
+        let rnd = runs(&[3; 20]);
+        let outcome = fix.test(&rnd, 20, 20, &TestMethod::Chi2).unwrap();
+        assert!(outcome.p < 0.05);
+        assert_eq!(outcome.positions.len(), 1);
+        assert_eq!(outcome.effect.tvd, 1.0);
+        assert_eq!(rnd.runs.mean(), 3.0);
+
+        let outcome = fix
+            .test(&runs(&[0; 20]), 20, 20, &TestMethod::Chi2)
+            .unwrap();
+        assert_eq!(outcome.p, 1.0);
+        assert_eq!(outcome.bits, 0.0);
+
+        assert!(WarpCost::default()
+            .test(&WarpCost::default(), 1, 1, &TestMethod::Ks)
+            .is_none());
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::{HashMap, HashSet};
+
+use monitor::cuda::KernelTy;
+use serde::Serialize;
+
+use crate::dtest::{SiteKind, TestResult};
+
+/// Units tested and how many of them pass or fail the threshold, untestable ones exist on one
+/// side only
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Count {
+    pub tested: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub untestable: usize,
+}
+
+impl Count {
+    fn test(&mut self, failed: bool) {
+        self.tested += 1;
+        if failed {
+            self.failed += 1;
+        } else {
+            self.passed += 1;
+        }
+    }
+}
+
+/// What an analysis tested, a report without leakage only means something if it tested anything
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Coverage {
+    /// Kernels, failed if any call of it leaks
+    pub kernels: Count,
+    /// Kernel calls, failed if any site leaks or the call is in a different share of the runs
+    pub calls: Count,
+    /// Control flow of nodes
+    pub nodes: Count,
+    /// Memory access instructions
+    pub instrs: Count,
+    /// Access positions of memory access instructions, compared before correction
+    pub positions: Count,
+    /// Shared memory access instructions whose bank conflicts are compared
+    pub bank: Count,
+    /// Global memory access instructions whose transactions are compared
+    pub coalescing: Count,
+}
+
+impl Coverage {
+    /// Count the units of `res` whose adjusted p value is below `threshold` as failed
+    pub fn new(res: &TestResult, threshold: f64) -> Self {
+        let mut coverage = Self::default();
+        // every kernel type, whether any of its calls is tested and whether any fails
+        let mut kernels: HashMap<KernelTy, (bool, bool)> = HashMap::new();
+
+        // calls in both evidence but in a different share of the runs
+        let mut unequal = HashSet::new();
+        for k in res.diff_kernel.iter() {
+            if k.l_num == 0 || k.r_num == 0 {
+                coverage.calls.untestable += 1;
+                kernels.entry(k.ty).or_default();
+            } else {
+                unequal.insert(k.ctx.to_string());
+            }
+        }
+
+        for k in res.eq_kernel.iter() {
+            let mut failed = unequal.contains(&k.ctx.to_string());
+
+            for cf in k.cf.iter() {
+                if cf.unpaired {
+                    coverage.nodes.untestable += 1;
+                    failed = true;
+                } else {
+                    coverage.nodes.test(cf.p_adjusted < threshold);
+                    failed |= cf.p_adjusted < threshold;
+                }
+            }
+
+            for df in k.df.iter() {
+                coverage.instrs.test(df.p_adjusted < threshold);
+                failed |= df.p_adjusted < threshold;
+
+                df.positions
+                    .iter()
+                    .for_each(|p| coverage.positions.test(*p < threshold));
+                coverage.positions.untestable += df.untestable_positions;
+            }
+            for (kind, cost) in k.costs() {
+                let count = if kind == SiteKind::Bank {
+                    &mut coverage.bank
+                } else {
+                    &mut coverage.coalescing
+                };
+                count.test(cost.p_adjusted < threshold);
+                failed |= cost.p_adjusted < threshold;
+            }
+            coverage.instrs.untestable += k.untestable_instrs;
+            coverage.positions.untestable += k.untestable_positions;
+
+            coverage.calls.test(failed);
+            let kernel = kernels.entry(k.ty).or_default();
+            kernel.0 = true;
+            kernel.1 |= failed;
+        }
+
+        for (tested, failed) in kernels.into_values() {
+            if tested {
+                coverage.kernels.test(failed);
+            } else {
+                coverage.kernels.untestable += 1;
+            }
+        }
+
+        coverage
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        dtest::{DiffKernelResult, EqKernelResult, NodeCfResult, NodeDfResult, TestResult},
+        trace::TraceCtx,
+    };
+
+    use super::{Count, Coverage};
+
+    fn count(tested: usize, passed: usize, failed: usize, untestable: usize) -> Count {
+        Count {
+            tested,
+            passed,
+            failed,
+            untestable,
+        }
+    }
+
+    #[test]
+    pub fn test_coverage() {
+        let mut res = TestResult::new();
+
+        let mut call = EqKernelResult::new().ty(1);
+        call.push_cf(NodeCfResult::new(0).p_value(0.5));
+        call.push_cf(NodeCfResult::new(1).p_value(0.01));
+        call.push_cf(NodeCfResult::new(2).p_value(0.1).unpaired());
+        call.push_df(
+            NodeDfResult::new(0, 3)
+                .p_value(0.2)
+                .positions(vec![0.2, 0.9], 1),
+        );
+        call.push_untestable(2, 5);
+        res.push_eq(call);
+
+        // a kernel without any difference
+        let mut call = EqKernelResult::new().ty(2);
+        call.push_cf(NodeCfResult::new(0).p_value(1.0));
+        res.push_eq(call);
+
+        // a kernel only launched by one side
+        res.push_diff(DiffKernelResult {
+            ty: 3,
+            ctx: TraceCtx::new(),
+            l_num: 2,
+            r_num: 0,
+            nondeterministic: false,
+        });
+
+        let coverage = Coverage::new(&res, 0.05);
+        assert_eq!(coverage.kernels, count(2, 1, 1, 1));
+        assert_eq!(coverage.calls, count(2, 1, 1, 1));
+        assert_eq!(coverage.nodes, count(3, 2, 1, 1));
+        assert_eq!(coverage.instrs, count(1, 1, 0, 2));
+        assert_eq!(coverage.positions, count(2, 2, 0, 6));
+
+        // nothing is tested, nothing leaks
+        assert_eq!(Coverage::new(&TestResult::new(), 0.05), Coverage::default());
+    }
+}
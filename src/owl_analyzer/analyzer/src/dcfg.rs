@@ -1,15 +1,17 @@
 use std::collections::BTreeMap;
 
-use monitor::{
-    cuda::{BBId, InstrId},
-    raw::RawNode,
-};
+use monitor::{cuda::BBId, raw::RawNode};
 
 use crate::{
-    dtest::{EqKernelResult, NodeCfResult, NodeDfResult},
+    contingency::{leaked_bits, Contingency},
+    cost::WarpCost,
+    dtest::{CfCell, EqKernelResult, NodeCfResult, NodeCostResult, NodeDfResult},
+    error::{Error, Result},
     hist::ks_test_p_value,
+    ltest::{Effect, LeakageTest, Sample},
     matrix::CfMatrix,
-    memory::MemAccessRecord,
+    memory::{MemAccessInstr, MemAccessRecord},
+    observation::Observations,
 };
 
 #[derive(
@@ -20,23 +22,95 @@ pub struct Direct {
     pub end: u32,
 }
 
+/// Transitions of a node kept in its control flow result
+const TOP_CELLS: usize = 3;
+
+#[derive(Clone)]
 pub struct Node {
     pub id: BBId,
     pub cf: CfMatrix,
     pub mem_access: MemAccessRecord,
+    /// Number of executions in every merged run
+    pub runs: Observations,
 }
 
 impl Node {
-    pub fn cf_test(&self, other: &Self, m: usize, n: usize) -> f64 {
-        self.cf.test(&other.cf, m, n)
+    /// Node of a single run
+    pub fn new(id: BBId, cf: CfMatrix, mem_access: MemAccessRecord) -> Self {
+        let runs = Observations::single(cf.total() as f64);
+        Self {
+            id,
+            cf,
+            mem_access,
+            runs,
+        }
     }
 
-    /// Implement data flow test and return p value of every memory access instruction
+    /// Test the control flow, per-run observations are padded to the `m` and `n` runs of
+    /// the two sides, the transitions contributing most to the difference are kept
+    pub fn cf_test(
+        &self,
+        other: &Self,
+        m: usize,
+        n: usize,
+        test: &dyn LeakageTest,
+    ) -> NodeCfResult {
+        let (lh, rh) = self.cf.aligned_counts(&other.cf);
+        let (l_runs, r_runs) = (self.runs.padded(m), other.runs.padded(n));
+        let (l, r) = (
+            Sample {
+                hist: &lh,
+                runs: &l_runs,
+            },
+            Sample {
+                hist: &rh,
+                runs: &r_runs,
+            },
+        );
+        let outcome = test.test(&l, &r);
+        let effect = Effect::new(&l, &r, outcome.statistic);
+
+        let bits = leaked_bits(&lh, &rh, &l_runs, &r_runs);
+        let contributions = Contingency::new(&lh, &rh).contributions();
+        let mut cells: Vec<_> = self
+            .cf
+            .cells(&other.cf)
+            .into_iter()
+            .zip(contributions)
+            .zip(lh.iter().zip(rh.iter()))
+            .filter(|(_, (l, r))| l != r)
+            .map(|(((from, to), contribution), (l, r))| CfCell {
+                from,
+                to,
+                fix: *l as usize,
+                rnd: *r as usize,
+                contribution,
+            })
+            .collect();
+        cells.sort_by(|a, b| b.contribution.total_cmp(&a.contribution));
+        cells.truncate(TOP_CELLS);
+
+        NodeCfResult::new(self.id)
+            .p_value(outcome.p)
+            .effect(effect)
+            .cells(cells)
+            .bits(bits)
+            .runs(l_runs, r_runs)
+    }
+
+    /// Implement data flow test and return the result of every memory access instruction,
+    /// per-run observations are padded to the `m` and `n` runs of the two sides
     ///
     /// Return None if there is no memory access instruction
-    pub fn df_test(&self, other: &Self, m: usize, n: usize) -> Option<Vec<(f64, InstrId)>> {
+    pub fn df_test(
+        &self,
+        other: &Self,
+        m: usize,
+        n: usize,
+        test: &dyn LeakageTest,
+    ) -> Result<Option<Vec<NodeDfResult>>> {
         if self.mem_access.instrs.is_empty() && other.mem_access.instrs.is_empty() {
-            return None;
+            return Ok(None);
         }
 
         log::debug!("Test mem access in node: {}", self.id);
@@ -46,9 +120,47 @@ impl Node {
             .instrs
             .iter()
             .zip(other.mem_access.instrs.iter())
-            .map(|(l, r)| (l.test(r, m, n), l.instr))
-            .collect();
-        Some(p)
+            .map(|(l, r)| {
+                let outcome = l.test(r, m, n, test)?;
+                Ok(NodeDfResult::new(self.id, l.instr)
+                    .p_value(outcome.p)
+                    .effect(outcome.effect)
+                    .bits(outcome.bits)
+                    .positions(outcome.positions, outcome.untestable)
+                    .addr(outcome.addr)
+                    .runs(l.runs.padded(m), r.runs.padded(n)))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Some(p))
+    }
+
+    /// Test the warp access `cost` of every memory access instruction having one, per-run
+    /// observations are padded to the `m` and `n` runs of the two sides
+    pub fn cost_test(
+        &self,
+        other: &Self,
+        m: usize,
+        n: usize,
+        test: &dyn LeakageTest,
+        cost: fn(&MemAccessInstr) -> &WarpCost,
+    ) -> Vec<NodeCostResult> {
+        self.mem_access
+            .instrs
+            .iter()
+            .zip(other.mem_access.instrs.iter())
+            .filter_map(|(l, r)| {
+                let (l_cost, r_cost) = (cost(l), cost(r));
+                let outcome = l_cost.test(r_cost, m, n, test)?;
+                Some(
+                    NodeCostResult::new(self.id, l.instr)
+                        .p_value(outcome.p)
+                        .effect(outcome.effect)
+                        .bits(outcome.bits)
+                        .positions(outcome.positions, outcome.untestable)
+                        .runs(l_cost.runs.padded(m), r_cost.runs.padded(n)),
+                )
+            })
+            .collect()
     }
 
     pub fn same(&self, other: &Self) -> bool {
@@ -62,6 +174,7 @@ impl Default for Node {
             id: Default::default(),
             cf: CfMatrix::default(),
             mem_access: MemAccessRecord::new(),
+            runs: Observations::default(),
         }
     }
 }
@@ -74,11 +187,11 @@ impl PartialEq for Node {
 
 impl From<RawNode> for Node {
     fn from(value: RawNode) -> Self {
-        Self {
-            id: value.id,
-            cf: CfMatrix::from_raw(value.control_flow),
-            mem_access: value.mem_access.into(),
-        }
+        Self::new(
+            value.id,
+            CfMatrix::from_raw(value.control_flow),
+            value.mem_access.into(),
+        )
     }
 }
 
@@ -86,9 +199,28 @@ impl Node {
     fn merge(&mut self, other: Self) {
         self.mem_access += other.mem_access;
         self.cf += other.cf;
+        self.runs.merge(other.runs);
     }
 }
 
+/// Effect of a node executed `l` and `r` times on the sides, one of them never
+fn unpaired_effect(l: usize, r: usize) -> Effect {
+    let runs = Observations::default();
+    let (lh, rh) = ([l as f64], [r as f64]);
+    Effect::new(
+        &Sample {
+            hist: &lh,
+            runs: &runs,
+        },
+        &Sample {
+            hist: &rh,
+            runs: &runs,
+        },
+        1.0,
+    )
+}
+
+#[derive(Clone)]
 pub struct TDcfg {
     pub nodes: BTreeMap<BBId, Node>,
 }
@@ -103,7 +235,14 @@ impl Default for TDcfg {
 }
 
 impl TDcfg {
-    pub fn test(self, other: Self, n: usize, m: usize, threshold: f64) -> EqKernelResult {
+    /// Test every node, results of all tested nodes and instructions are kept
+    pub fn test(
+        self,
+        other: Self,
+        n: usize,
+        m: usize,
+        test: &dyn LeakageTest,
+    ) -> Result<EqKernelResult> {
         use crate::align::VecAlign;
 
         let mut res = EqKernelResult::new();
@@ -113,50 +252,89 @@ impl TDcfg {
         let rn: Vec<_> = other.nodes.values().collect();
 
         let (l, r) = ln.align(&rn);
-        assert_eq!(l.len(), r.len());
+        if l.len() != r.len() {
+            return Err(Error::Align(format!(
+                "{} nodes are aligned with {} nodes",
+                l.len(),
+                r.len()
+            )));
+        }
 
-        l.into_iter().zip(r.into_iter()).for_each(|(l, r)| {
+        for (l, r) in l.into_iter().zip(r) {
             match (l, r) {
                 (Some(l), Some(r)) => {
                     // control flow test
                     log::debug!("Eq node");
-                    let p = l.cf_test(r, n, m);
-                    if p < threshold {
-                        res.push_cf(
-                            NodeCfResult::new(l.id).p_value(p), // .l_flow(l.cf.clone())
-                                                                // .r_flow(r.cf.clone()),
-                        );
-                    }
+                    res.push_cf(
+                        l.cf_test(r, n, m, test), // .l_flow(l.cf.clone())
+                                                  // .r_flow(r.cf.clone()),
+                    );
 
                     // data flow test
-                    if let Some(dfp) = l.df_test(r, n, m) {
-                        dfp.into_iter()
-                            .filter(|(p, _)| *p < threshold)
-                            .for_each(|(p, instr)| {
-                                res.push_df(
-                                    NodeDfResult::new(l.id, instr).p_value(p), // .fix_mem(l.mem_access.clone())
-                                                                               // .rnd_mem(r.mem_access.clone()),
-                                )
-                            })
+                    if let Some(df) = l.df_test(r, n, m, test)? {
+                        df.into_iter().for_each(|df| res.push_df(df))
                     };
+
+                    // bank conflict and coalescing test
+                    l.cost_test(r, n, m, test, |instr| &instr.conflicts)
+                        .into_iter()
+                        .for_each(|bank| res.push_bank(bank));
+                    l.cost_test(r, n, m, test, |instr| &instr.transactions)
+                        .into_iter()
+                        .for_each(|coalescing| res.push_coalescing(coalescing));
+
+                    // instructions are paired in order, the rest of the longer side is not tested
+                    let paired = l.mem_access.instrs.len().min(r.mem_access.instrs.len());
+                    for side in [&l.mem_access, &r.mem_access] {
+                        let (instrs, positions) = side.count_from(paired);
+                        res.push_untestable(instrs, positions);
+                    }
                 }
                 (None, Some(r)) => {
                     log::debug!("Uneq node, left is None");
+                    let (instrs, positions) = r.mem_access.count_from(0);
+                    res.push_untestable(instrs, positions);
                     let p = ks_test_p_value(1.0, n, m);
-                    res.push_cf(NodeCfResult::new(r.id).p_value(p));
+                    res.push_cf(
+                        NodeCfResult::new(r.id)
+                            .p_value(p)
+                            .unpaired()
+                            .effect(unpaired_effect(0, r.cf.total()))
+                            .bits(leaked_bits(
+                                &[],
+                                &[],
+                                &Observations::default().padded(n),
+                                &r.runs.padded(m),
+                            ))
+                            .runs(Observations::default().padded(n), r.runs.padded(m)),
+                    );
                 }
                 (Some(l), None) => {
                     log::debug!("Uneq node, right is None");
+                    let (instrs, positions) = l.mem_access.count_from(0);
+                    res.push_untestable(instrs, positions);
                     let p = ks_test_p_value(1.0, n, m);
-                    res.push_cf(NodeCfResult::new(l.id).p_value(p));
+                    res.push_cf(
+                        NodeCfResult::new(l.id)
+                            .p_value(p)
+                            .unpaired()
+                            .effect(unpaired_effect(l.cf.total(), 0))
+                            .bits(leaked_bits(
+                                &[],
+                                &[],
+                                &l.runs.padded(n),
+                                &Observations::default().padded(m),
+                            ))
+                            .runs(l.runs.padded(n), Observations::default().padded(m)),
+                    );
                 }
                 (None, None) => {
-                    panic!("Never happened")
+                    return Err(Error::Align("a node is aligned with no node".to_owned()));
                 }
             }
-        });
+        }
 
-        res
+        Ok(res)
     }
 
     pub fn same(&self, other: &Self) -> bool {
@@ -204,3 +382,58 @@ impl TDcfg {
         });
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Node;
+    use crate::{ltest::TestMethod, matrix::CfMatrix, memory::MemAccessRecord};
+
+    /// Node merged from `runs` runs, each entering it 4 times from the entry to `to`
+    fn node(to: isize, runs: usize) -> Node {
+        let single = || {
+            let mut cf = CfMatrix::default();
+            cf.insert_cf(-1, to, 4);
+            Node::new(0, cf, MemAccessRecord { instrs: Vec::new() })
+        };
+        (1..runs).fold(single(), |mut node, _| {
+            node.merge(single());
+            node
+        })
+    }
+
+    #[test]
+    pub fn test_merged_runs() {
+        let (l, r) = (node(1, 2), node(2, 5));
+        // every run is one observation, the node is executed 4 times per run
+        let summary = l.runs.summary();
+        assert_eq!((summary.n, summary.mean, summary.variance), (2, 4.0, 0.0));
+        assert_eq!(l.cf.total(), 8);
+
+        // the executions per run are the same on both sides
+        let res = l.cf_test(&r, 2, 5, &TestMethod::Welch);
+        assert_eq!((res.l_runs.len(), res.r_runs.len()), (2, 5));
+        assert_eq!(res.p_value, 1.0);
+
+        // runs without the node count as zero executions
+        let res = l.cf_test(&r, 3, 5, &TestMethod::Welch);
+        assert_eq!(res.l_runs.summary().n, 3);
+        assert!(res.p_value < 1.0);
+    }
+
+    #[test]
+    pub fn test_cf_cells() {
+        let res = node(1, 2).cf_test(&node(2, 5), 2, 5, &TestMethod::Chi2);
+        // both transitions differ, one per side
+        let cells: Vec<_> = res
+            .cells
+            .iter()
+            .map(|c| (c.from, c.to, c.fix, c.rnd))
+            .collect();
+        assert_eq!(cells, vec![(-1, 1, 8, 0), (-1, 2, 0, 20)]);
+        assert!(res.cells[0].contribution >= res.cells[1].contribution);
+
+        // transitions taken as often on both sides aren't kept
+        let res = node(1, 2).cf_test(&node(1, 2), 2, 2, &TestMethod::Chi2);
+        assert!(res.cells.is_empty());
+    }
+}
@@ -1,13 +1,23 @@
 use monitor::cuda::{BBId, InstrId, KernelTy};
 
-use crate::{matrix::CfMatrix, memory::MemAccessRecord, trace::TraceCtx};
+use crate::{
+    correction::Correction,
+    error::Result,
+    ltest::{Effect, LeakageTest},
+    matrix::CfMatrix,
+    memory::{MemAccessRecord, TargetAddr},
+    observation::Observations,
+    trace::TraceCtx,
+};
 
 // pub trait KernelTest {
 //     fn test(&self, other: &Self, n: usize, m: usize) -> KernelResult;
 // }
 
 pub trait DeviceTest {
-    fn test(self, other: Self, n: usize, m: usize, threshold: f64) -> TestResult;
+    /// `n` and `m` are the numbers of fixed and random input runs merged into the two sides,
+    /// every site is compared by `test`
+    fn test(self, other: Self, n: usize, m: usize, test: &dyn LeakageTest) -> Result<TestResult>;
 }
 
 /// Represent kernel leakage
@@ -17,6 +27,8 @@ pub struct DiffKernelResult {
     pub ctx: TraceCtx,
     pub l_num: usize,
     pub r_num: usize,
+    /// Launched a different number of times under identical inputs too
+    pub nondeterministic: bool,
 }
 
 #[derive(Debug)]
@@ -25,6 +37,13 @@ pub struct EqKernelResult {
     pub ctx: TraceCtx,
     pub cf: Vec<NodeCfResult>,
     pub df: Vec<NodeDfResult>,
+    /// Bank conflict replays of shared memory access instructions
+    pub bank: Vec<NodeCostResult>,
+    /// Transactions of global memory access instructions
+    pub coalescing: Vec<NodeCostResult>,
+    /// Memory access instructions of one side only, and their access positions
+    pub untestable_instrs: usize,
+    pub untestable_positions: usize,
     // pub name: String,
 }
 
@@ -35,6 +54,10 @@ impl EqKernelResult {
             ctx: TraceCtx::new(),
             cf: Default::default(),
             df: Default::default(),
+            bank: Default::default(),
+            coalescing: Default::default(),
+            untestable_instrs: 0,
+            untestable_positions: 0,
             // name: String::new(),
         }
     }
@@ -57,6 +80,39 @@ impl EqKernelResult {
     pub fn push_df(&mut self, node_df: NodeDfResult) {
         self.df.push(node_df);
     }
+
+    pub fn push_bank(&mut self, node_bank: NodeCostResult) {
+        self.bank.push(node_bank);
+    }
+
+    pub fn push_coalescing(&mut self, node_coalescing: NodeCostResult) {
+        self.coalescing.push(node_coalescing);
+    }
+
+    /// Results of every warp access cost, with the kind of their site
+    pub fn costs(&self) -> impl Iterator<Item = (SiteKind, &NodeCostResult)> {
+        let bank = self.bank.iter().map(|res| (SiteKind::Bank, res));
+        let coalescing = self
+            .coalescing
+            .iter()
+            .map(|res| (SiteKind::Coalescing, res));
+        bank.chain(coalescing)
+    }
+
+    pub fn costs_mut(&mut self) -> impl Iterator<Item = (SiteKind, &mut NodeCostResult)> {
+        let bank = self.bank.iter_mut().map(|res| (SiteKind::Bank, res));
+        let coalescing = self
+            .coalescing
+            .iter_mut()
+            .map(|res| (SiteKind::Coalescing, res));
+        bank.chain(coalescing)
+    }
+
+    /// Count memory access instructions `instrs` of one side only with their `positions`
+    pub fn push_untestable(&mut self, instrs: usize, positions: usize) {
+        self.untestable_instrs += instrs;
+        self.untestable_positions += positions;
+    }
 }
 
 #[derive(Debug)]
@@ -80,6 +136,106 @@ impl TestResult {
     pub fn push_diff(&mut self, kernel: DiffKernelResult) {
         self.diff_kernel.push(kernel);
     }
+
+    /// Every tested site of the equal kernels, unpaired nodes are not tested
+    pub fn sites(&self) -> impl Iterator<Item = Site<'_>> {
+        self.eq_kernel.iter().flat_map(|k| {
+            let cf = k.cf.iter().filter(|cf| !cf.unpaired).map(|cf| Site {
+                ty: k.ty,
+                ctx: &k.ctx,
+                kind: SiteKind::Cf,
+                bb: cf.id,
+                instr: None,
+                p_value: cf.p_value,
+                p_adjusted: cf.p_adjusted,
+                bits: cf.bits,
+            });
+            let df = k.df.iter().map(|df| Site {
+                ty: k.ty,
+                ctx: &k.ctx,
+                kind: SiteKind::Df,
+                bb: df.id,
+                instr: Some(df.instr),
+                p_value: df.p_value,
+                p_adjusted: df.p_adjusted,
+                bits: df.bits,
+            });
+            let cost = k.costs().map(|(kind, cost)| Site {
+                ty: k.ty,
+                ctx: &k.ctx,
+                kind,
+                bb: cost.id,
+                instr: Some(cost.instr),
+                p_value: cost.p_value,
+                p_adjusted: cost.p_adjusted,
+                bits: cost.bits,
+            });
+            cf.chain(df).chain(cost)
+        })
+    }
+
+    /// Adjust p values of every tested site as one family
+    pub fn correct(&mut self, correction: Correction) {
+        let p: Vec<_> = self.sites().map(|site| site.p_value).collect();
+        let mut adjusted = correction.adjust(&p).into_iter();
+
+        for k in self.eq_kernel.iter_mut() {
+            for cf in k.cf.iter_mut().filter(|cf| !cf.unpaired) {
+                cf.p_adjusted = adjusted.next().unwrap_or(cf.p_value);
+            }
+            for df in k.df.iter_mut() {
+                df.p_adjusted = adjusted.next().unwrap_or(df.p_value);
+            }
+            for (_, cost) in k.costs_mut() {
+                cost.p_adjusted = adjusted.next().unwrap_or(cost.p_value);
+            }
+        }
+    }
+}
+
+/// What is compared at a site
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Default, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SiteKind {
+    /// Control flow of a node
+    #[default]
+    Cf,
+    /// Addresses accessed by a memory access instruction
+    Df,
+    /// Bank conflict replays of a shared memory access instruction
+    Bank,
+    /// Transactions of a global memory access instruction
+    Coalescing,
+}
+
+/// A tested site, control flow of node `bb` if `instr` is None, else a memory access instruction
+#[derive(Debug)]
+pub struct Site<'a> {
+    pub ty: KernelTy,
+    pub ctx: &'a TraceCtx,
+    pub kind: SiteKind,
+    pub bb: BBId,
+    pub instr: Option<InstrId>,
+    pub p_value: f64,
+    pub p_adjusted: f64,
+    pub bits: f64,
+}
+
+/// Identity of a site that is kept between results of different evidence
+pub type SiteKey = (String, KernelTy, SiteKind, BBId, Option<InstrId>);
+
+impl Site<'_> {
+    pub fn key(&self) -> SiteKey {
+        (
+            self.ctx.to_string(),
+            self.ty,
+            self.kind,
+            self.bb,
+            self.instr,
+        )
+    }
 }
 
 // #[derive(Debug)]
@@ -147,13 +303,41 @@ impl TestResult {
 //     pub p: f64,
 // }
 
+/// A `(from, to)` transition of a node and its share of the difference of both sides
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct CfCell {
+    pub from: isize,
+    pub to: isize,
+    pub fix: usize,
+    pub rnd: usize,
+    /// chi-squared contribution of the cell
+    pub contribution: f64,
+}
+
 #[derive(Debug)]
 pub struct NodeCfResult {
     pub id: BBId,
     pub p_value: f64,
+    /// p value after multiple-testing correction
+    pub p_adjusted: f64,
+    /// node only exists on one side, always a leakage
+    pub unpaired: bool,
+    /// Statistic and effect sizes of the compared transitions
+    pub effect: Effect,
+    /// Differs under identical inputs too
+    pub nondeterministic: bool,
 
     pub l_flow: CfMatrix,
     pub r_flow: CfMatrix,
+    /// Executions of the node in every fixed and random input run
+    pub l_runs: Observations,
+    pub r_runs: Observations,
+    /// Cells contributing most to the difference of the transition tables
+    pub cells: Vec<CfCell>,
+    /// Bits leaked about the input class
+    pub bits: f64,
+    /// Bootstrap confidence interval of `bits`
+    pub bits_ci: Option<(f64, f64)>,
 }
 
 impl NodeCfResult {
@@ -161,14 +345,44 @@ impl NodeCfResult {
         Self {
             id,
             p_value: 0.0,
+            p_adjusted: 0.0,
+            unpaired: false,
+            effect: Effect::default(),
+            nondeterministic: false,
 
             l_flow: Default::default(),
             r_flow: Default::default(),
+            l_runs: Default::default(),
+            r_runs: Default::default(),
+            cells: Vec::new(),
+            bits: 0.0,
+            bits_ci: None,
         }
     }
 
     pub fn p_value(mut self, p: f64) -> Self {
         self.p_value = p;
+        self.p_adjusted = p;
+        self
+    }
+
+    pub fn effect(mut self, effect: Effect) -> Self {
+        self.effect = effect;
+        self
+    }
+
+    pub fn cells(mut self, cells: Vec<CfCell>) -> Self {
+        self.cells = cells;
+        self
+    }
+
+    pub fn bits(mut self, bits: f64) -> Self {
+        self.bits = bits;
+        self
+    }
+
+    pub fn unpaired(mut self) -> Self {
+        self.unpaired = true;
         self
     }
 
@@ -181,6 +395,12 @@ impl NodeCfResult {
         self.r_flow = flow;
         self
     }
+
+    pub fn runs(mut self, l: Observations, r: Observations) -> Self {
+        self.l_runs = l;
+        self.r_runs = r;
+        self
+    }
 }
 
 #[derive(Debug)]
@@ -188,8 +408,27 @@ pub struct NodeDfResult {
     pub id: BBId,
     pub instr: InstrId,
     pub p_value: f64,
+    /// p value after multiple-testing correction
+    pub p_adjusted: f64,
     pub ld: MemAccessRecord,
     pub rd: MemAccessRecord,
+    /// Statistic and effect sizes of the access position with the smallest p value
+    pub effect: Effect,
+    /// p value of every access position of both sides, before correction
+    pub positions: Vec<f64>,
+    /// Access positions of one side only
+    pub untestable_positions: usize,
+    /// Address whose share differs most at the position with the smallest p value
+    pub addr: Option<TargetAddr>,
+    /// Differs under identical inputs too
+    pub nondeterministic: bool,
+    /// Accesses of the instruction in every fixed and random input run
+    pub l_runs: Observations,
+    pub r_runs: Observations,
+    /// Bits leaked about the input class, the most of any access position
+    pub bits: f64,
+    /// Bootstrap confidence interval of `bits`
+    pub bits_ci: Option<(f64, f64)>,
 }
 
 impl NodeDfResult {
@@ -198,8 +437,18 @@ impl NodeDfResult {
             id,
             instr,
             p_value: 0.0,
+            p_adjusted: 0.0,
             ld: MemAccessRecord::new(),
             rd: MemAccessRecord::new(),
+            effect: Effect::default(),
+            positions: Vec::new(),
+            untestable_positions: 0,
+            addr: None,
+            nondeterministic: false,
+            l_runs: Default::default(),
+            r_runs: Default::default(),
+            bits: 0.0,
+            bits_ci: None,
             // l_flow: Default::default(),
             // r_flow: Default::default(),
         }
@@ -207,6 +456,23 @@ impl NodeDfResult {
 
     pub fn p_value(mut self, p: f64) -> Self {
         self.p_value = p;
+        self.p_adjusted = p;
+        self
+    }
+
+    pub fn effect(mut self, effect: Effect) -> Self {
+        self.effect = effect;
+        self
+    }
+
+    pub fn positions(mut self, p: Vec<f64>, untestable: usize) -> Self {
+        self.positions = p;
+        self.untestable_positions = untestable;
+        self
+    }
+
+    pub fn addr(mut self, addr: Option<TargetAddr>) -> Self {
+        self.addr = addr;
         self
     }
 
@@ -219,6 +485,90 @@ impl NodeDfResult {
         self.rd = d;
         self
     }
+
+    pub fn runs(mut self, l: Observations, r: Observations) -> Self {
+        self.l_runs = l;
+        self.r_runs = r;
+        self
+    }
+
+    pub fn bits(mut self, bits: f64) -> Self {
+        self.bits = bits;
+        self
+    }
+}
+
+/// Cost of the warp accesses of a memory access instruction, its bank conflict replays or
+/// memory transactions
+#[derive(Debug)]
+pub struct NodeCostResult {
+    pub id: BBId,
+    pub instr: InstrId,
+    pub p_value: f64,
+    /// p value after multiple-testing correction
+    pub p_adjusted: f64,
+    /// Statistic and effect sizes of the access position with the smallest p value
+    pub effect: Effect,
+    /// p value of every access position of both sides, before correction
+    pub positions: Vec<f64>,
+    /// Access positions of one side only
+    pub untestable_positions: usize,
+    /// Differs under identical inputs too
+    pub nondeterministic: bool,
+    /// Summed cost of the instruction in every fixed and random input run
+    pub l_runs: Observations,
+    pub r_runs: Observations,
+    /// Bits leaked about the input class, the most of any access position
+    pub bits: f64,
+    /// Bootstrap confidence interval of `bits`
+    pub bits_ci: Option<(f64, f64)>,
+}
+
+impl NodeCostResult {
+    pub fn new(id: BBId, instr: InstrId) -> Self {
+        Self {
+            id,
+            instr,
+            p_value: 0.0,
+            p_adjusted: 0.0,
+            effect: Effect::default(),
+            positions: Vec::new(),
+            untestable_positions: 0,
+            nondeterministic: false,
+            l_runs: Default::default(),
+            r_runs: Default::default(),
+            bits: 0.0,
+            bits_ci: None,
+        }
+    }
+
+    pub fn p_value(mut self, p: f64) -> Self {
+        self.p_value = p;
+        self.p_adjusted = p;
+        self
+    }
+
+    pub fn effect(mut self, effect: Effect) -> Self {
+        self.effect = effect;
+        self
+    }
+
+    pub fn positions(mut self, p: Vec<f64>, untestable: usize) -> Self {
+        self.positions = p;
+        self.untestable_positions = untestable;
+        self
+    }
+
+    pub fn runs(mut self, l: Observations, r: Observations) -> Self {
+        self.l_runs = l;
+        self.r_runs = r;
+        self
+    }
+
+    pub fn bits(mut self, bits: f64) -> Self {
+        self.bits = bits;
+        self
+    }
 }
 
 // pub enum TestResult {
@@ -0,0 +1,57 @@
+use std::fmt;
+
+/// Errors of loading, merging and testing recorded traces
+#[derive(Debug)]
+pub enum Error {
+    /// A trace file of a run can't be read or parsed
+    Trace(monitor::Error),
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    /// A trace is readable but its content is inconsistent
+    Schema(String),
+    /// Kernel calls aligned together don't match
+    Align(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Trace(e) => write!(f, "{e}"),
+            Error::Io(e) => write!(f, "{e}"),
+            Error::Parse(e) => write!(f, "{e}"),
+            Error::Schema(msg) => write!(f, "invalid trace: {msg}"),
+            Error::Align(msg) => write!(f, "inconsistent alignment: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Trace(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Parse(e) => Some(e),
+            Error::Schema(_) | Error::Align(_) => None,
+        }
+    }
+}
+
+impl From<monitor::Error> for Error {
+    fn from(value: monitor::Error) -> Self {
+        Error::Trace(value)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error::Parse(value)
+    }
+}
@@ -1,7 +1,11 @@
+use monitor::cuda::KernelTy;
+
 use crate::{
     align::VecAlignOwned,
     dtest::{DeviceTest, DiffKernelResult, TestResult},
+    error::{Error, Result},
     kernel::KernelCall,
+    ltest::LeakageTest,
     trace::Trace,
 };
 
@@ -20,7 +24,8 @@ impl Default for Evidence {
 }
 
 impl Evidence {
-    pub fn merge_trace(&mut self, mut trace: Trace) {
+    /// Merge the trace of one more run, kernel calls are aligned by their context
+    pub fn merge_trace(&mut self, mut trace: Trace) -> Result<()> {
         // assert_eq!(self.ctx, other.ctx);
         log::debug!("Merge new trace");
 
@@ -33,45 +38,60 @@ impl Evidence {
         // align kernel call
         let (la, ra) = la.align_own(ra);
 
-        assert_eq!(la.len(), ra.len());
+        if la.len() != ra.len() {
+            return Err(Error::Align(format!(
+                "{} calls aligned with {} calls",
+                la.len(),
+                ra.len()
+            )));
+        }
         log::debug!("aligned length: {}", la.len());
         // log::debug!("la: {}")
 
         let kernels = la
             .into_iter()
-            .zip(ra.into_iter())
+            .zip(ra)
             .map(|(l, r)| match (l, r) {
                 // same kernel call, further merge
                 (Some(mut l), Some(r)) => {
                     // assert_eq!(l.trace.addr, r.trace.addr);
                     log::debug!("Eq kernel call");
-                    assert_eq!(r.num, 1);
+                    if r.num != 1 {
+                        return Err(Error::Align(format!(
+                            "call at `{}` of a single run is merged {} times",
+                            r.ctx, r.num
+                        )));
+                    }
                     l.num += r.num;
-                    l.merge_owned(r);
-                    l
+                    l.merge_owned(r)?;
+                    Ok(l)
                 }
                 // call in evidence, but not in the new trace
                 (Some(l), None) => {
                     log::debug!("Noeq kernel call");
-                    l
+                    Ok(l)
                 }
                 // call not in evidence, but in the new trace
                 (None, Some(r)) => {
                     log::debug!("New kernel call");
-                    r
-                }
-                _ => {
-                    panic!()
+                    Ok(r)
                 }
+                (None, None) => Err(Error::Align("call missing on both sides".to_owned())),
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>>>()?;
 
         self.kernels = kernels;
+        Ok(())
+    }
+
+    /// Keep only the kernel calls whose kernel type satisfies `f`
+    pub fn retain_kernels(&mut self, f: impl Fn(KernelTy) -> bool) {
+        self.kernels.retain(|k| f(k.trace.ty));
     }
 }
 
 impl DeviceTest for Evidence {
-    fn test(self, other: Self, n: usize, m: usize, threshold: f64) -> TestResult {
+    fn test(self, other: Self, n: usize, m: usize, test: &dyn LeakageTest) -> Result<TestResult> {
         let mut res = TestResult::new();
 
         // align kernel by ctx
@@ -79,19 +99,31 @@ impl DeviceTest for Evidence {
 
         log::debug!("Aligned two evidence");
 
-        l.into_iter().zip(r.into_iter()).for_each(|(l, r)| {
+        for (l, r) in l.into_iter().zip(r) {
             match (l, r) {
                 (Some(l), Some(r)) => {
                     log::debug!("Eq kernel call");
-                    if l.num != r.num {
+                    if l.num < n || r.num < m {
+                        log::debug!(
+                            "call at `{}` is in {}/{n} fix and {}/{m} rnd runs",
+                            l.ctx,
+                            l.num,
+                            r.num
+                        );
+                    }
+                    // a call in a different share of the runs, sides may have unequal runs
+                    if l.num * m != r.num * n {
                         res.push_diff(DiffKernelResult {
                             ty: l.trace.ty,
                             ctx: l.ctx.clone(),
                             l_num: l.num,
                             r_num: r.num,
+                            nondeterministic: false,
                         })
                     }
-                    let eq = l.test_owned(r, n, m, threshold);
+                    // a call missing from some runs is tested on the runs it is in
+                    let (l_num, r_num) = (l.num, r.num);
+                    let eq = l.test_owned(r, l_num, r_num, test)?;
                     res.push_eq(eq);
                 }
                 (Some(l), None) => {
@@ -102,6 +134,7 @@ impl DeviceTest for Evidence {
                         ctx: l.ctx.clone(),
                         l_num: l.num,
                         r_num: 0,
+                        nondeterministic: false,
                     })
                 }
                 (None, Some(r)) => {
@@ -112,14 +145,48 @@ impl DeviceTest for Evidence {
                         ctx: r.ctx.clone(),
                         l_num: 0,
                         r_num: r.num,
+                        nondeterministic: false,
                     })
                 }
                 (None, None) => {
-                    panic!()
+                    return Err(Error::Align("call missing on both sides".to_owned()));
                 }
             }
-        });
+        }
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Evidence;
+    use crate::{dtest::DeviceTest, fixture::Kernel, ltest::TestMethod};
+
+    /// Evidence of `runs` runs of the kernel jumping to `to`
+    fn evidence(to: isize, runs: usize) -> Evidence {
+        let mut evidence = Evidence::default();
+        for _ in 0..runs {
+            let trace = Kernel::new().node(to).raw().try_into().unwrap();
+            evidence.merge_trace(trace).unwrap();
+        }
+        evidence
+    }
+
+    #[test]
+    pub fn test_asymmetric_runs() {
+        // the kernel is called in every run of both sides, 2 and 5 runs are not a difference
+        let res = evidence(1, 2)
+            .test(evidence(2, 5), 2, 5, &TestMethod::Ks)
+            .unwrap();
+        assert!(res.diff_kernel.is_empty());
+        assert_eq!(res.eq_kernel.len(), 1);
 
-        res
+        // called in 4 of the 5 random input runs
+        let res = evidence(1, 2)
+            .test(evidence(2, 4), 2, 5, &TestMethod::Ks)
+            .unwrap();
+        let diff: Vec<_> = res.diff_kernel.iter().map(|k| (k.l_num, k.r_num)).collect();
+        assert_eq!(diff, vec![(2, 4)]);
     }
 }
@@ -0,0 +1,427 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    os::unix::process::{CommandExt, ExitStatusExt},
+    path::Path,
+    process::{Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Environment variable telling the monitor where to dump the trace
+pub const OWL_TRACE: &str = "OWL_TRACE";
+
+pub const STATUS_FILE: &str = "status.json";
+pub const STDOUT_FILE: &str = "stdout.log";
+pub const STDERR_FILE: &str = "stderr.log";
+pub const KERNEL_FILE: &str = "kernel.json";
+
+/// How executions are run and supervised
+#[derive(Debug, Clone)]
+pub struct Monitor {
+    /// Extra monitor environment (e.g. `OWL_PIPE2`, `OWL_FILE`) of every execution
+    pub envs: Vec<(String, String)>,
+    /// Maximum number of executions recorded at the same time
+    pub jobs: usize,
+    /// Kill an execution running longer than this
+    pub timeout: Option<Duration>,
+    /// Execute a failed run again at most this many times
+    pub retries: usize,
+    /// Skip runs completed by a previous campaign
+    pub resume: bool,
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self {
+            envs: Vec::new(),
+            jobs: 1,
+            timeout: None,
+            retries: 0,
+            resume: false,
+        }
+    }
+}
+
+/// Outcome of a run, saved to `status.json` in its trace directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RunStatus {
+    pub cmd: String,
+    pub attempts: usize,
+    pub exit_code: Option<i32>,
+    pub signal: Option<i32>,
+    pub timed_out: bool,
+    /// `kernel.json` was produced
+    pub traced: bool,
+    pub elapsed_ms: u64,
+    /// The command could not be executed
+    pub error: Option<String>,
+}
+
+impl RunStatus {
+    /// The run neither crashed nor missed its trace, a non-zero exit code alone is accepted
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none() && !self.timed_out && self.signal.is_none() && self.traced
+    }
+
+    pub fn save(&self, trace_path: &str) -> std::io::Result<()> {
+        let file = File::create(format!("{trace_path}/{STATUS_FILE}"))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn load(trace_path: &str) -> std::io::Result<Self> {
+        let reader = BufReader::new(File::open(format!("{trace_path}/{STATUS_FILE}"))?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+/// Whether the trace in `trace_path` can be merged into evidence,
+/// runs recorded without a status only need their `kernel.json`
+pub fn is_mergeable(trace_path: &str) -> bool {
+    let traced = Path::new(trace_path).join(KERNEL_FILE).is_file();
+
+    match RunStatus::load(trace_path) {
+        Ok(status) => status.is_ok() && traced,
+        Err(_) => traced,
+    }
+}
+
+/// Whether the run in `trace_path` completed, it has an ok `status.json` as completion marker
+/// and its `kernel.json` is well-formed json (not truncated by a killed campaign)
+pub fn is_complete(trace_path: &str) -> bool {
+    if !RunStatus::load(trace_path).is_ok_and(|status| status.is_ok()) {
+        return false;
+    }
+
+    File::open(Path::new(trace_path).join(KERNEL_FILE))
+        .map(|file| {
+            serde_json::from_reader::<_, serde::de::IgnoredAny>(BufReader::new(file)).is_ok()
+        })
+        .unwrap_or(false)
+}
+
+/// One execution of the tested program, traced into `trace_path`
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub cmd: String,
+    pub stage: String,
+    pub idx: usize,
+    pub trace_path: String,
+}
+
+impl Run {
+    pub fn new(root_path: &str, stage: &str, idx: usize, cmd: &str) -> Self {
+        Self {
+            cmd: cmd.to_owned(),
+            stage: stage.to_owned(),
+            idx,
+            trace_path: format!("{root_path}/{stage}/{idx}/"),
+        }
+    }
+
+    /// Prepare trace directory and execute the command with its own monitor environment,
+    /// failed executions are retried up to `monitor.retries` times.
+    /// Fail only if the trace directory can't be created
+    pub fn exec(&self, monitor: &Monitor) -> std::io::Result<RunStatus> {
+        log::info!("Recorded trace path: {}", self.trace_path);
+        std::fs::create_dir_all(&self.trace_path)?;
+
+        let mut status = RunStatus::default();
+
+        for attempt in 1..=monitor.retries + 1 {
+            status = self.exec_once(monitor);
+            status.attempts = attempt;
+
+            if let Err(e) = status.save(&self.trace_path) {
+                log::warn!("failed to save status of {}: {e}", self.trace_path);
+            }
+
+            if status.is_ok() {
+                if status.exit_code != Some(0) {
+                    log::warn!("{} exited with {:?}", self.trace_path, status.exit_code);
+                }
+                break;
+            }
+
+            log::warn!(
+                "run {} failed (attempt {attempt}/{}): {:?}",
+                self.trace_path,
+                monitor.retries + 1,
+                status
+            );
+        }
+
+        Ok(status)
+    }
+
+    fn exec_once(&self, monitor: &Monitor) -> RunStatus {
+        let mut status = RunStatus {
+            cmd: self.cmd.clone(),
+            ..Default::default()
+        };
+
+        // trace of a previous attempt must not be taken for this one
+        let kernel = Path::new(&self.trace_path).join(KERNEL_FILE);
+        let _ = std::fs::remove_file(&kernel);
+
+        let start = Instant::now();
+        match exec(&self.cmd, &self.trace_path, monitor) {
+            Ok((exit, timed_out)) => {
+                status.exit_code = exit.code();
+                status.signal = exit.signal();
+                status.timed_out = timed_out;
+            }
+            Err(e) => status.error = Some(e.to_string()),
+        }
+        status.elapsed_ms = start.elapsed().as_millis() as u64;
+        status.traced = kernel.is_file();
+
+        status
+    }
+}
+
+/// Execute `runs` with at most `monitor.jobs` child processes at the same time
+pub fn record_runs(runs: &[Run], monitor: &Monitor) -> std::io::Result<Vec<RunStatus>> {
+    log::info!("run {} times, {} jobs", runs.len(), monitor.jobs);
+
+    let next = AtomicUsize::new(0);
+    let workers = monitor.jobs.clamp(1, runs.len().max(1));
+
+    let mut status: Vec<_> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                s.spawn(|| -> std::io::Result<_> {
+                    let mut status = Vec::new();
+                    loop {
+                        let n = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(run) = runs.get(n) else {
+                            break;
+                        };
+
+                        if monitor.resume && is_complete(&run.trace_path) {
+                            log::info!("skip completed run {}", run.trace_path);
+                            let done = RunStatus::load(&run.trace_path).unwrap_or_default();
+                            status.push((n, done));
+                            continue;
+                        }
+
+                        println!(
+                            "-------------- {} {} ({}/{}) --------------",
+                            run.stage,
+                            run.idx,
+                            n + 1,
+                            runs.len()
+                        );
+
+                        status.push((n, run.exec(monitor)?));
+                    }
+                    Ok(status)
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<std::io::Result<Vec<_>>>()
+    })?
+    .into_iter()
+    .flatten()
+    .collect();
+    status.sort_by_key(|(n, _)| *n);
+
+    let failed = status.iter().filter(|(_, s)| !s.is_ok()).count();
+    if failed > 0 {
+        log::warn!("{failed} of {} runs failed", runs.len());
+    }
+
+    Ok(status.into_iter().map(|(_, s)| s).collect())
+}
+
+/// Execute `cmd` in its own process group, stdout and stderr are saved into `trace_path`,
+/// return the exit status and whether it was killed for timeout.
+/// On timeout the whole group is killed, not only the shell running `cmd`
+fn exec(
+    cmd: &str,
+    trace_path: &str,
+    monitor: &Monitor,
+) -> std::io::Result<(std::process::ExitStatus, bool)> {
+    log::debug!("execute test program");
+
+    let stdout = File::create(format!("{trace_path}/{STDOUT_FILE}"))?;
+    let stderr = File::create(format!("{trace_path}/{STDERR_FILE}"))?;
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env(OWL_TRACE, trace_path)
+        .envs(monitor.envs.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(stderr)
+        .process_group(0)
+        .spawn()?;
+
+    let start = Instant::now();
+    loop {
+        if let Some(exit) = child.try_wait()? {
+            return Ok((exit, false));
+        }
+
+        if monitor.timeout.is_some_and(|t| start.elapsed() >= t) {
+            log::warn!("{trace_path} timeout, kill");
+            // SAFETY: killpg only signals the group led by the child, which is not reaped yet
+            if unsafe { libc::killpg(child.id() as libc::pid_t, libc::SIGKILL) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            return Ok((child.wait()?, true));
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{is_complete, is_mergeable, record_runs, Monitor, Run, RunStatus};
+    use crate::fixture::TempDir;
+
+    #[test]
+    pub fn test_record_runs_concurrently() {
+        let dir = TempDir::new("exec");
+        let root = dir.path();
+
+        let cmd = "sleep 0.5; echo $OWL_STUB > $OWL_TRACE/stub";
+        let runs: Vec<_> = (0..4)
+            .map(|idx| Run::new(root, if idx % 2 == 0 { "fix" } else { "rnd" }, idx, cmd))
+            .collect();
+
+        let monitor = Monitor {
+            envs: vec![("OWL_STUB".to_owned(), "stub".to_owned())],
+            jobs: 4,
+            ..Default::default()
+        };
+
+        let start = Instant::now();
+        record_runs(&runs, &monitor).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(1500));
+
+        for run in runs.iter() {
+            let content = std::fs::read_to_string(format!("{}/stub", run.trace_path)).unwrap();
+            assert_eq!(content.trim(), "stub");
+        }
+    }
+
+    #[test]
+    pub fn test_supervise() {
+        let dir = TempDir::new("supervise");
+        let root = dir.path();
+
+        let monitor = Monitor {
+            timeout: Some(Duration::from_millis(300)),
+            retries: 2,
+            ..Default::default()
+        };
+
+        let ok = Run::new(
+            root,
+            "fix",
+            0,
+            "echo out; echo err >&2; echo {} > $OWL_TRACE/kernel.json",
+        );
+        // fail on the first attempt only
+        let flaky = Run::new(
+            root,
+            "fix",
+            1,
+            "[ -f $OWL_TRACE/tried ] && echo {} > $OWL_TRACE/kernel.json; touch $OWL_TRACE/tried",
+        );
+        let hang = Run::new(root, "fix", 2, "exec sleep 5");
+        let crash = Run::new(
+            root,
+            "fix",
+            3,
+            "echo {} > $OWL_TRACE/kernel.json; kill -9 $$",
+        );
+        let status = record_runs(&[ok.clone(), flaky, hang, crash], &monitor).unwrap();
+
+        assert!(status[0].is_ok());
+        assert_eq!((status[0].attempts, status[0].exit_code), (1, Some(0)));
+        let stdout = std::fs::read_to_string(format!("{}/stdout.log", ok.trace_path)).unwrap();
+        let stderr = std::fs::read_to_string(format!("{}/stderr.log", ok.trace_path)).unwrap();
+        assert_eq!((stdout.trim(), stderr.trim()), ("out", "err"));
+
+        assert!(status[1].is_ok());
+        assert_eq!(status[1].attempts, 2);
+
+        assert!(!status[2].is_ok());
+        assert!(status[2].timed_out);
+        assert_eq!(status[2].attempts, 3);
+
+        assert!(!status[3].is_ok());
+        assert_eq!(status[3].signal, Some(9));
+
+        assert!(is_mergeable(&format!("{root}/fix/0")));
+        assert!(is_mergeable(&format!("{root}/fix/1")));
+        assert!(!is_mergeable(&format!("{root}/fix/2")));
+        assert!(!is_mergeable(&format!("{root}/fix/3")));
+        assert_eq!(
+            RunStatus::load(&format!("{root}/fix/3")).unwrap().attempts,
+            3
+        );
+    }
+
+    #[test]
+    pub fn test_kill_group() {
+        let dir = TempDir::new("kill_group");
+        let root = dir.path();
+
+        let monitor = Monitor {
+            timeout: Some(Duration::from_millis(300)),
+            ..Default::default()
+        };
+        // the traced program is a grandchild, forked by the shell
+        let run = Run::new(
+            root,
+            "fix",
+            0,
+            "(sleep 1; echo {} > $OWL_TRACE/kernel.json) & wait",
+        );
+        let status = record_runs(std::slice::from_ref(&run), &monitor).unwrap();
+        assert!(status[0].timed_out);
+
+        // a surviving grandchild would write its trace by now
+        std::thread::sleep(Duration::from_millis(1500));
+        assert!(!std::path::Path::new(&format!("{}/kernel.json", run.trace_path)).exists());
+    }
+
+    #[test]
+    pub fn test_resume() {
+        let dir = TempDir::new("resume");
+        let root = dir.path();
+
+        let mut monitor = Monitor::default();
+        let count = format!("{root}/count");
+        let cmd = format!("echo x >> {count}; echo {{}} > $OWL_TRACE/kernel.json");
+        let runs: Vec<_> = (0..3).map(|idx| Run::new(root, "fix", idx, &cmd)).collect();
+
+        record_runs(&runs[..2], &monitor).unwrap();
+        // truncated trace of a killed campaign
+        std::fs::write(format!("{root}/fix/1/kernel.json"), "{").unwrap();
+        assert!(is_complete(&format!("{root}/fix/0")));
+        assert!(!is_complete(&format!("{root}/fix/1")));
+
+        monitor.resume = true;
+        let status = record_runs(&runs, &monitor).unwrap();
+        assert!(status.iter().all(|s| s.is_ok()));
+
+        // run 0 is reused, run 1 and 2 are executed
+        let count = std::fs::read_to_string(count).unwrap();
+        assert_eq!(count.lines().count(), 4);
+    }
+}
@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+
+/// Kernels taken into the leakage test, a pattern matches every kernel name containing it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KernelFilter {
+    /// Test only kernels matching one of these patterns, every kernel if empty
+    pub include: Vec<String>,
+    /// Never test kernels matching one of these patterns
+    pub exclude: Vec<String>,
+}
+
+impl KernelFilter {
+    pub fn is_tested(&self, name: &str) -> bool {
+        let included =
+            self.include.is_empty() || self.include.iter().any(|p| name.contains(p.as_str()));
+
+        included && !self.exclude.iter().any(|p| name.contains(p.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KernelFilter;
+
+    #[test]
+    pub fn test_kernel_filter() {
+        assert!(KernelFilter::default().is_tested("aes_encrypt"));
+
+        let filter = KernelFilter {
+            include: vec!["aes".to_owned(), "rsa".to_owned()],
+            exclude: vec!["init".to_owned()],
+        };
+        assert!(filter.is_tested("aes_encrypt"));
+        assert!(filter.is_tested("rsa_mod_exp"));
+        assert!(!filter.is_tested("aes_init_key"));
+        assert!(!filter.is_tested("memcpy_kernel"));
+    }
+}
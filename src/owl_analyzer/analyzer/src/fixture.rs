@@ -0,0 +1,167 @@
+//! Trace directories and traces of a single kernel, written like the monitor dumps them
+
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use monitor::raw::{RawData, RawTrace};
+use serde_json::{json, Value};
+
+/// Directory under the system temp directory, removed with its content when dropped
+pub struct TempDir(String);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "owl_{name}_{}_{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+
+        Self(path.to_str().unwrap().to_owned())
+    }
+
+    pub fn path(&self) -> &str {
+        &self.0
+    }
+
+    pub fn join(&self, sub: &str) -> String {
+        format!("{}/{sub}", self.0)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Trace of kernel `k` launched once, every node is entered 4 times
+#[derive(Debug, Clone, Default)]
+pub struct Kernel {
+    pools: Vec<Value>,
+    nodes: Vec<Value>,
+    allocs: Vec<Value>,
+    contexts: Vec<Value>,
+}
+
+impl Kernel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A memory pool of `size` bytes at `addr` live at the launch
+    pub fn pool(mut self, addr: u64, size: u64) -> Self {
+        self.pools.push(json!({"addr": addr, "size": size}));
+        self
+    }
+
+    /// A node jumping from the entry to `to`
+    pub fn node(mut self, to: isize) -> Self {
+        self.nodes.push(json!({
+            "id": self.nodes.len(),
+            "control_flow": [{"from": -1, "to": to, "num": 4}],
+            "mem_access": [],
+        }));
+        self
+    }
+
+    /// Instruction 16 of the last node accessing `(addr, count)` of memory type `ty`
+    pub fn access(mut self, ty: &str, memory: &[(u64, usize)]) -> Self {
+        let memory: Vec<_> = memory
+            .iter()
+            .map(|(addr, count)| json!({"addr": addr, "count": count}))
+            .collect();
+        let node = self.nodes.last_mut().expect("access of no node");
+        node["mem_access"] = json!([{
+            "addr": 16,
+            "data": [{"pos": 0, "access": [{"type": ty, "memory": memory}]}],
+        }]);
+        self
+    }
+
+    /// Instruction 16 of the last node accessing memory type `ty` by the warp accesses
+    /// `(addrs, count)`, each recorded `count` times
+    pub fn warps(mut self, ty: &str, warps: &[(&[u64], usize)]) -> Self {
+        let mut memory: BTreeMap<u64, usize> = BTreeMap::new();
+        for (addrs, count) in warps {
+            for addr in *addrs {
+                *memory.entry(*addr).or_default() += count;
+            }
+        }
+        let memory: Vec<_> = memory.into_iter().collect();
+        self = self.access(ty, &memory);
+
+        let warps: Vec<_> = warps
+            .iter()
+            .map(|(addrs, count)| json!({"addr": addrs, "count": count}))
+            .collect();
+        let node = self.nodes.last_mut().unwrap();
+        node["mem_access"][0]["data"][0]["access"][0]["warps"] = json!(warps);
+        self
+    }
+
+    /// A host allocation of `size` bytes at `addr` by `cudaMalloc`, called from `frames`
+    /// given as `(func, file, line)`
+    pub fn alloc(mut self, addr: u64, size: u64, frames: &[(&str, &str, u64)]) -> Self {
+        self.allocs
+            .push(json!({"addr": addr, "name": "cudaMalloc", "size": size}));
+        let frames: Vec<_> = frames
+            .iter()
+            .enumerate()
+            .map(|(idx, (func, file, line))| {
+                json!({"addr": idx + 1, "func": func, "file": file, "offset": line})
+            })
+            .collect();
+        self.contexts.push(json!({"addr": addr, "bt": frames}));
+        self
+    }
+
+    /// Content of `kernel.json`
+    pub fn json(&self) -> String {
+        json!({
+            "type": "Kernel",
+            "data": [{
+                "id": 0,
+                "ty": 1,
+                "name": "k",
+                "bt": [],
+                "mp": self.pools,
+                "g": {"nodes": self.nodes},
+            }],
+        })
+        .to_string()
+    }
+
+    /// The trace as loaded from a run directory
+    pub fn raw(&self) -> RawTrace {
+        let data: RawData = serde_json::from_str(&self.json()).unwrap();
+        RawTrace {
+            kernels: data.get_kernel().unwrap(),
+            context: serde_json::from_value(json!(self.contexts)).unwrap(),
+            alloc: serde_json::from_value(json!(self.allocs)).unwrap(),
+        }
+    }
+
+    /// Command dumping the trace like the monitor does
+    pub fn stub(&self) -> String {
+        format!("echo '{}' > $OWL_TRACE/kernel.json", self.json())
+    }
+
+    /// Write the trace as run `idx` of `stage` under `root`, with the host allocations if any
+    pub fn write(&self, root: &str, stage: &str, idx: usize) {
+        let path = format!("{root}/{stage}/{idx}");
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(format!("{path}/kernel.json"), self.json()).unwrap();
+
+        if !self.allocs.is_empty() {
+            let alloc = json!({"type": "Alloc", "data": self.allocs});
+            std::fs::write(format!("{path}/alloc.json"), alloc.to_string()).unwrap();
+            let context = json!({"type": "Context", "data": self.contexts});
+            std::fs::write(format!("{path}/context.json"), context.to_string()).unwrap();
+        }
+    }
+}
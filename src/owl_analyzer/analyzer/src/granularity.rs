@@ -0,0 +1,119 @@
+use monitor::cuda::MemType;
+use serde::{Deserialize, Serialize};
+
+use crate::memory::TargetAddr;
+
+/// Bytes of a global memory sector
+pub(crate) const SECTOR: u64 = 32;
+/// Bytes of an L1 cache line
+const LINE: u64 = 128;
+/// Bytes of a shared memory bank word
+pub(crate) const BANK_WIDTH: u64 = 4;
+/// Shared memory banks
+pub(crate) const BANKS: u64 = 32;
+
+/// Unit accesses to one type of memory are observed in, offsets in the same unit are the same
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    /// Exact byte offset
+    Byte,
+    /// 32-byte sector
+    Sector,
+    /// 128-byte L1 cache line
+    Line,
+    /// Shared memory bank of 4-byte words, one of 32
+    Bank,
+}
+
+impl Bucket {
+    /// First offset of the unit `offset` is in, or the index of its bank
+    pub fn apply(&self, offset: u64) -> u64 {
+        match self {
+            Bucket::Byte => offset,
+            Bucket::Sector => offset / SECTOR * SECTOR,
+            Bucket::Line => offset / LINE * LINE,
+            Bucket::Bank => offset / BANK_WIDTH % BANKS,
+        }
+    }
+}
+
+/// Address abstraction applied to every memory access, so that only differences an attacker
+/// can observe are tested
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Granularity {
+    /// Global, generic and global to shared memory
+    pub global: Bucket,
+    pub shared: Bucket,
+    pub local: Bucket,
+    pub constant: Bucket,
+}
+
+impl Default for Granularity {
+    fn default() -> Self {
+        Self {
+            global: Bucket::Line,
+            shared: Bucket::Bank,
+            local: Bucket::Byte,
+            constant: Bucket::Byte,
+        }
+    }
+}
+
+impl Granularity {
+    /// Exact byte offsets of every memory type
+    pub fn exact() -> Self {
+        Self {
+            global: Bucket::Byte,
+            shared: Bucket::Byte,
+            local: Bucket::Byte,
+            constant: Bucket::Byte,
+        }
+    }
+
+    pub fn bucket(&self, ty: MemType) -> Bucket {
+        match ty {
+            MemType::Global | MemType::Generic | MemType::GlobalToShared => self.global,
+            MemType::Shared => self.shared,
+            MemType::Local => self.local,
+            MemType::Constant => self.constant,
+            _ => Bucket::Byte,
+        }
+    }
+
+    pub fn apply(&self, mut addr: TargetAddr) -> TargetAddr {
+        addr.offset = self.bucket(addr.ty).apply(addr.offset);
+        addr
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use monitor::cuda::MemType;
+
+    use super::{Bucket, Granularity};
+    use crate::memory::TargetAddr;
+
+    #[test]
+    pub fn test_granularity() {
+        assert_eq!(Bucket::Sector.apply(70), 64);
+        assert_eq!(Bucket::Line.apply(127), 0);
+        assert_eq!(Bucket::Line.apply(300), 256);
+        // words 4 bytes apart are in neighbouring banks, 128 bytes apart in the same one
+        assert_eq!(Bucket::Bank.apply(4), 1);
+        assert_eq!(Bucket::Bank.apply(128 + 6), 1);
+
+        let granularity = Granularity::default();
+        let addr = |offset, ty| granularity.apply(TargetAddr::new(offset, None, ty));
+        assert_eq!(addr(200, MemType::Generic).offset, 128);
+        assert_eq!(addr(200, MemType::Shared).offset, 18);
+        assert_eq!(addr(200, MemType::Local).offset, 200);
+        assert_eq!(
+            Granularity::exact()
+                .apply(TargetAddr::new(200, None, MemType::Global))
+                .offset,
+            200
+        );
+    }
+}
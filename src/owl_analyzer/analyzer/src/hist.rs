@@ -1,7 +1,321 @@
-use std::f64::consts::E;
+use std::f64::consts::PI;
 
+/// Largest `m * n` whose p value is computed from the exact distribution
+const EXACT_LIMIT: usize = 10_000;
+
+/// p value of the two-sample KS test, the probability of a statistic `>= x` when both
+/// samples of size `m` and `n` are drawn from the same distribution.
+/// Small samples use the exact distribution, large ones the Kolmogorov distribution.
 pub fn ks_test_p_value(x: f64, m: usize, n: usize) -> f64 {
-    let m = m as f64;
-    let n = n as f64;
-    2f64 * E.powf(-2f64 * x.powi(2) * ((n * m) / (n + m)))
+    if m == 0 || n == 0 || x <= 0.0 {
+        return 1.0;
+    }
+
+    let p = if m * n <= EXACT_LIMIT {
+        ks_exact_p_value(x, m, n)
+    } else {
+        let (mf, nf) = (m as f64, n as f64);
+        kolmogorov_sf(x * (mf * nf / (mf + nf)).sqrt())
+    };
+
+    p.clamp(0.0, 1.0)
+}
+
+/// Count the lattice paths from `(0, 0)` to `(m, n)` staying strictly below `x`,
+/// every path is an equally likely merge of the two sorted samples
+fn ks_exact_p_value(x: f64, m: usize, n: usize) -> f64 {
+    let mn = (m * n) as f64;
+    // |i / m - j / n| >= x, scaled by m * n to compare integers
+    let bound = x * mn - 1e-9 * mn;
+    let outside = |i: usize, j: usize| (i * n).abs_diff(j * m) as f64 >= bound;
+
+    // paths[j] holds the inside paths to (i, j) of the current row i
+    let mut paths = vec![0f64; n + 1];
+    for i in 0..=m {
+        for j in 0..=n {
+            paths[j] = if outside(i, j) {
+                0.0
+            } else if i == 0 && j == 0 {
+                1.0
+            } else {
+                let up = if i > 0 { paths[j] } else { 0.0 };
+                let left = if j > 0 { paths[j - 1] } else { 0.0 };
+                up + left
+            };
+        }
+    }
+
+    1.0 - paths[n] / binomial(m + n, m)
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    let k = k.min(n - k);
+    (0..k).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+/// Survival function of the Kolmogorov distribution, the full series is summed until
+/// its terms vanish, small `lambda` uses the faster converging Jacobi form
+fn kolmogorov_sf(lambda: f64) -> f64 {
+    if lambda <= 0.0 {
+        return 1.0;
+    }
+
+    if lambda < 1.18 {
+        // P(K <= lambda) = sqrt(2 pi) / lambda * sum exp(-(2k - 1)^2 pi^2 / (8 lambda^2))
+        let w = PI * PI / (8.0 * lambda * lambda);
+        let mut cdf = 0.0;
+        for k in 1..=100 {
+            let term = (-((2 * k - 1) as f64).powi(2) * w).exp();
+            cdf += term;
+            if term < 1e-16 * cdf {
+                break;
+            }
+        }
+        1.0 - (2.0 * PI).sqrt() / lambda * cdf
+    } else {
+        // P(K > lambda) = 2 sum (-1)^(k - 1) exp(-2 k^2 lambda^2)
+        let mut sf = 0.0;
+        for k in 1..=100 {
+            let term = (-2.0 * (k * k) as f64 * lambda * lambda).exp();
+            sf += if k % 2 == 1 { term } else { -term };
+            if term < 1e-16 * sf {
+                break;
+            }
+        }
+        2.0 * sf
+    }
+}
+
+/// Two-sided p value of Student's t distribution with `df` degrees of freedom,
+/// `P(|T| >= |t|) = I_x(df / 2, 1 / 2)` with `x = df / (df + t^2)`
+pub fn student_t_p_value(t: f64, df: f64) -> f64 {
+    if df.is_nan() || df <= 0.0 || t.is_nan() {
+        return 1.0;
+    }
+    if t.is_infinite() {
+        return 0.0;
+    }
+
+    inc_beta(df / 2.0, 0.5, df / (df + t * t)).clamp(0.0, 1.0)
+}
+
+/// p value of the chi-squared distribution with `df` degrees of freedom, `P(X >= x)`
+pub fn chi2_p_value(x: f64, df: f64) -> f64 {
+    if df.is_nan() || df <= 0.0 || x.is_nan() || x <= 0.0 {
+        return 1.0;
+    }
+
+    upper_gamma(df / 2.0, x / 2.0).clamp(0.0, 1.0)
+}
+
+/// Regularized upper incomplete gamma function `Q(a, x)`
+fn upper_gamma(a: f64, x: f64) -> f64 {
+    let front = (-x + a * x.ln() - ln_gamma(a)).exp();
+
+    if x < a + 1.0 {
+        // series of the lower function P(a, x)
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for k in 1..=1000 {
+            term *= x / (a + k as f64);
+            sum += term;
+            if term.abs() < sum.abs() * 1e-16 {
+                break;
+            }
+        }
+        1.0 - front * sum
+    } else {
+        // continued fraction of Q(a, x), modified Lentz method
+        const TINY: f64 = 1e-300;
+        let guard = |v: f64| if v.abs() < TINY { TINY } else { v };
+
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / TINY;
+        let mut d = 1.0 / guard(b);
+        let mut h = d;
+        for k in 1..=1000 {
+            let an = -(k as f64) * (k as f64 - a);
+            b += 2.0;
+            d = 1.0 / guard(an * d + b);
+            c = guard(b + an / c);
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        front * h
+    }
+}
+
+/// Regularized incomplete beta function `I_x(a, b)`
+fn inc_beta(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp();
+
+    // the continued fraction converges fast below the mean, use the symmetry above it
+    if x < (a + 1.0) / (a + b + 2.0) {
+        front * beta_cf(a, b, x) / a
+    } else {
+        1.0 - front * beta_cf(b, a, 1.0 - x) / b
+    }
+}
+
+/// Continued fraction of the incomplete beta function, evaluated by the modified Lentz method
+fn beta_cf(a: f64, b: f64, x: f64) -> f64 {
+    const TINY: f64 = 1e-300;
+    let guard = |v: f64| if v.abs() < TINY { TINY } else { v };
+
+    let mut c = 1.0;
+    let mut d = 1.0 / guard(1.0 - (a + b) * x / (a + 1.0));
+    let mut h = d;
+
+    for m in 1..=300 {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+
+        // even step
+        let num = m * (b - m) * x / ((a + m2 - 1.0) * (a + m2));
+        d = 1.0 / guard(1.0 + num * d);
+        c = guard(1.0 + num / c);
+        h *= d * c;
+
+        // odd step
+        let num = -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1.0));
+        d = 1.0 / guard(1.0 + num * d);
+        c = guard(1.0 + num / c);
+        let delta = d * c;
+        h *= delta;
+
+        if (delta - 1.0).abs() < 1e-15 {
+            break;
+        }
+    }
+
+    h
+}
+
+/// Natural logarithm of the gamma function for `x > 0`, Lanczos approximation
+pub fn ln_gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEF: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x < 0.5 {
+        // reflection formula
+        return (PI / (PI * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+
+    let x = x - 1.0;
+    let t = x + G + 0.5;
+    let sum = COEF
+        .iter()
+        .enumerate()
+        .skip(1)
+        .fold(COEF[0], |acc, (i, c)| acc + c / (x + i as f64));
+
+    0.5 * (2.0 * PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{chi2_p_value, kolmogorov_sf, ks_test_p_value, ln_gamma, student_t_p_value};
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    pub fn test_ks_exact() {
+        // exact two-sample tables, P(D >= x)
+        assert_close(ks_test_p_value(1.0, 2, 2), 1.0 / 3.0);
+        assert_close(ks_test_p_value(1.0, 3, 3), 0.1);
+        assert_close(ks_test_p_value(0.6, 5, 5), 5.0 / 14.0);
+        assert_close(ks_test_p_value(0.4, 5, 5), 55.0 / 63.0);
+        assert_close(ks_test_p_value(0.5, 8, 8), 1819.0 / 6435.0);
+        assert_close(ks_test_p_value(0.75, 3, 4), 8.0 / 35.0);
+        assert_close(ks_test_p_value(0.5, 4, 6), 58.0 / 105.0);
+        assert_close(ks_test_p_value(0.625, 6, 8), 278.0 / 3003.0);
+
+        // order of the samples does not matter
+        assert_close(ks_test_p_value(0.5, 6, 4), 58.0 / 105.0);
+    }
+
+    #[test]
+    pub fn test_ks_bounds() {
+        assert_eq!(ks_test_p_value(0.0, 2, 2), 1.0);
+        assert_eq!(ks_test_p_value(0.5, 0, 3), 1.0);
+
+        for (m, n) in [(2, 2), (3, 7), (20, 20), (200, 300)] {
+            for x in [0.0, 0.01, 0.1, 0.5, 0.9, 1.0] {
+                let p = ks_test_p_value(x, m, n);
+                assert!((0.0..=1.0).contains(&p), "p({x}, {m}, {n}) = {p}");
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_kolmogorov_series() {
+        // reference values of the Kolmogorov distribution
+        assert_close(kolmogorov_sf(0.5), 0.9639452436648751);
+        assert_close(kolmogorov_sf(1.0), 0.26999967167735456);
+        assert_close(kolmogorov_sf(1.5), 0.022217962616525127);
+        assert!((kolmogorov_sf(1.3581) - 0.05).abs() < 1e-4);
+        assert!((kolmogorov_sf(1.6276) - 0.01).abs() < 1e-4);
+
+        // both forms agree where they switch
+        let below = kolmogorov_sf(1.18 - 1e-12);
+        let above = kolmogorov_sf(1.18);
+        assert!((below - above).abs() < 1e-9);
+
+        // large samples approach the asymptotic distribution
+        let p = ks_test_p_value(0.1, 1000, 1000);
+        assert_close(p, kolmogorov_sf(0.1 * 500f64.sqrt()));
+    }
+
+    #[test]
+    pub fn test_student_t() {
+        assert_close(ln_gamma(1.0), 0.0);
+        assert_close(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln());
+        assert_close(ln_gamma(10.0), 362880f64.ln());
+
+        // two-sided reference values
+        assert_close(student_t_p_value(1.0, 1.0), 0.5);
+        assert_close(student_t_p_value(2.0, 10.0), 0.07338803477073863);
+        assert_close(student_t_p_value(-2.0, 10.0), 0.07338803477073863);
+        assert_close(student_t_p_value(2.228138851986, 10.0), 0.05);
+
+        assert_eq!(student_t_p_value(0.0, 5.0), 1.0);
+        assert_eq!(student_t_p_value(f64::INFINITY, 5.0), 0.0);
+        assert_eq!(student_t_p_value(1.0, 0.0), 1.0);
+    }
+
+    #[test]
+    pub fn test_chi2() {
+        // closed forms, erfc(sqrt(x / 2)) and exp(-x / 2)
+        assert_close(chi2_p_value(3.0, 1.0), 0.08326451666355043);
+        assert_close(chi2_p_value(5.0, 2.0), (-2.5f64).exp());
+        assert_close(chi2_p_value(0.5, 2.0), (-0.25f64).exp());
+        assert_close(chi2_p_value(3.841458820694124, 1.0), 0.05);
+        assert_close(chi2_p_value(40.0, 2.0), (-20f64).exp());
+
+        assert_eq!(chi2_p_value(0.0, 3.0), 1.0);
+        assert_eq!(chi2_p_value(1.0, 0.0), 1.0);
+    }
 }
@@ -1,10 +1,11 @@
 use crate::{
     dtest::EqKernelResult,
-    merge::Merge,
+    error::{Error, Result},
+    ltest::LeakageTest,
     trace::{KernelTrace, TraceCtx},
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct KernelCall {
     pub ctx: TraceCtx,
     pub trace: KernelTrace,
@@ -39,15 +40,34 @@ impl KernelCall {
         }
     }
 
-    pub fn test_owned(self, other: Self, n: usize, m: usize, threshold: f64) -> EqKernelResult {
-        assert_eq!(self.ctx, other.ctx);
-        assert_eq!(self.trace.ty, other.trace.ty);
+    pub fn test_owned(
+        self,
+        other: Self,
+        n: usize,
+        m: usize,
+        test: &dyn LeakageTest,
+    ) -> Result<EqKernelResult> {
+        self.check(&other)?;
 
-        self.trace.test(other.trace, n, m, threshold).ctx(self.ctx)
+        Ok(self.trace.test(other.trace, n, m, test)?.ctx(self.ctx))
     }
 
-    pub fn merge_owned(&mut self, other: Self) {
-        self.trace.merge_own(other.trace);
+    pub fn merge_owned(&mut self, other: Self) -> Result<()> {
+        self.check(&other)?;
+
+        self.trace.merge_owned(other.trace)
+    }
+
+    /// Calls aligned together must be the same kernel launched at the same context
+    fn check(&self, other: &Self) -> Result<()> {
+        if self.ctx != other.ctx || self.trace.ty != other.trace.ty {
+            return Err(Error::Align(format!(
+                "kernel {} at `{}` is aligned with kernel {} at `{}`",
+                self.trace.ty, self.ctx, other.trace.ty, other.ctx
+            )));
+        }
+
+        Ok(())
     }
 
     pub fn same(&self, other: &Self) -> bool {
@@ -1,167 +1,743 @@
 use std::{
-    collections::HashMap,
-    io::{BufRead, BufReader},
-    process::Stdio,
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::Range,
     rc::Rc,
 };
 
+use adaptive::SAMPLING_FILE;
+use calibration::Baseline;
+use dtest::TestResult;
 use evidence::Evidence;
+use exec::{is_mergeable, record_runs, Run};
 use monitor::{cuda::KernelTy, DataAcceptor};
 pub use trace::Trace;
 
 // mod cf;
+mod adaptive;
+mod calibration;
+mod cost;
+mod coverage;
+mod error;
 mod evidence;
+mod filter;
+#[cfg(test)]
+mod fixture;
+mod granularity;
 mod hist;
 mod memory;
-mod merge;
 mod myers_diff;
+mod observation;
+mod permutation;
 mod report;
-pub use report::Report;
+pub use adaptive::{Adaptive, Sampling};
+pub use alloc::{Allocation, Buffer, PoolId};
+pub use calibration::Calibration;
+pub use correction::Correction;
+pub use cost::Coalescing;
+pub use coverage::{Count, Coverage};
+pub use error::{Error, Result};
+pub use exec::{Monitor, RunStatus};
+pub use filter::KernelFilter;
+pub use granularity::{Bucket, Granularity};
+pub use ltest::{LeakageTest, Sample, TestMethod};
+pub use permutation::Permutation;
+pub use report::{Report, ReportFormat};
+pub use schedule::{Schedule, ScheduleMode};
 
 use crate::dtest::DeviceTest;
 mod alloc;
 mod dcfg;
 mod dtest;
+mod exec;
 mod kernel;
+mod ltest;
 mod matrix;
+mod rng;
+mod schedule;
 mod trace;
 // pub use trace::Trace;
 
 mod align;
+mod contingency;
+mod correction;
 
 pub struct Analyzer {
     // pub pipe_path: String,
     pub fix_cmd: String,
     pub rnd_cmd: String,
-    pub times: usize,
+    /// Number of fixed input runs
+    pub fix_times: usize,
+    /// Number of random input runs
+    pub rnd_times: usize,
     pub threshold: f64,
 
     pub trace_path: String,
     pub kernels: HashMap<KernelTy, Rc<String>>,
+
+    /// How every execution is run and supervised
+    pub monitor: Monitor,
+    /// Execution order of fixed and random input runs
+    pub schedule: ScheduleMode,
+    /// Seed of the interleaved schedule, random if not set
+    pub seed: Option<u64>,
+    /// Kernels taken into the leakage test
+    pub filter: KernelFilter,
+    /// Multiple-testing correction over every tested site
+    pub correction: Correction,
+    /// Test comparing every control flow and data flow site
+    pub method: TestMethod,
+    /// Fail loading when a run has a bad trace instead of skipping it
+    pub abort_on_bad_run: bool,
+    /// Empirical p values and bootstrap intervals from resampled runs
+    pub permutation: Option<Permutation>,
+    /// How sites differing between fixed input runs are reported
+    pub calibration: Calibration,
+    /// Runs of a second identical fixed input, recorded to `trace_path/calib`
+    pub calibration_times: usize,
+    /// Unit accessed addresses of every memory type are compared in
+    pub granularity: Granularity,
+    /// Sector size global memory transactions are counted in
+    pub coalescing: Coalescing,
+    /// Memory pools of the loaded traces, named by their allocation
+    pub buffers: BTreeMap<PoolId, Buffer>,
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Self {
+            fix_cmd: String::new(),
+            rnd_cmd: String::new(),
+            fix_times: 0,
+            rnd_times: 0,
+            threshold: 2.0,
+            trace_path: String::new(),
+            kernels: Default::default(),
+            monitor: Monitor::default(),
+            schedule: ScheduleMode::default(),
+            seed: None,
+            filter: KernelFilter::default(),
+            correction: Correction::default(),
+            method: TestMethod::default(),
+            abort_on_bad_run: false,
+            permutation: None,
+            calibration: Calibration::default(),
+            calibration_times: 0,
+            granularity: Granularity::default(),
+            coalescing: Coalescing::default(),
+            buffers: BTreeMap::new(),
+        }
+    }
 }
 
 impl Analyzer {
-    pub fn run_fix(&mut self) -> Evidence {
-        log::info!("run {} times", self.times);
+    /// Execute fixed input command `fix_times` times, traces are recorded to `trace_path/fix`
+    pub fn record_fix(&self) -> Result<()> {
+        record_runs(
+            &self.runs("fix", &self.fix_cmd, self.fix_times),
+            &self.monitor,
+        )?;
+        Ok(())
+    }
 
-        let mut evidence = Evidence::default();
+    /// Execute random input command `rnd_times` times, traces are recorded to `trace_path/rnd`
+    pub fn record_rnd(&self) -> Result<()> {
+        record_runs(
+            &self.runs("rnd", &self.rnd_cmd, self.rnd_times),
+            &self.monitor,
+        )?;
+        Ok(())
+    }
 
-        // let mut dict = HashMap::new();
+    /// Execute fixed and random input commands in the order of `schedule`,
+    /// the schedule is saved to `trace_path/schedule.json`.
+    /// The fixed input command is run `calibration_times` more times to `trace_path/calib`
+    pub fn record(&self) -> Result<()> {
+        self.remove_stale(self.fix_times, self.rnd_times, self.calibration_times)?;
+        match std::fs::remove_file(format!("{}/{SAMPLING_FILE}", self.trace_path)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        self.record_range(0..self.fix_times, 0..self.rnd_times, self.seed)?;
 
-        for idx in 0..self.times {
-            println!("-------------- {}/{} --------------", idx + 1, self.times);
+        if self.calibration_times > 0 {
+            record_runs(
+                &self.runs("calib", &self.fix_cmd, self.calibration_times),
+                &self.monitor,
+            )?;
+        }
+        Ok(())
+    }
 
-            // prepare env, dir before execution
-            let acceptor = prepare(&self.trace_path, "fix", idx);
+    /// Record fixed and random input runs in rounds of `adaptive.step` until every tested site
+    /// is decided or `adaptive.max` runs of both inputs are recorded, the first round records
+    /// `fix_times` and `rnd_times` runs, the summary is saved to `sampling.json`
+    pub fn record_adaptive(&mut self, adaptive: &Adaptive) -> Result<Sampling> {
+        let seed = self
+            .seed
+            .or_else(|| (self.schedule == ScheduleMode::Interleaved).then(rng::Rng::seed));
+
+        let max = adaptive.max.max(1);
+        let mut recorded = (0, 0);
+        let mut target = (self.fix_times.clamp(1, max), self.rnd_times.clamp(1, max));
+        let mut rounds = 0;
+        // calibration runs are never recorded adaptively
+        self.remove_stale(target.0, target.1, 0)?;
+
+        loop {
+            rounds += 1;
+            self.record_range(recorded.0..target.0, recorded.1..target.1, seed)?;
+            recorded = target;
+
+            let (fix, n) = self.load("fix")?;
+            let (rnd, m) = self.load("rnd")?;
+            let res = self.test_evidence(fix, rnd, n, m)?;
+
+            let mut sampling = Sampling::new(adaptive, &res, &self.kernels, self.threshold);
+            log::info!(
+                "round {rounds}: {n} fix runs, {m} rnd runs, {} undecided sites",
+                sampling.undecided.len()
+            );
+
+            if sampling.is_decided() || (recorded.0 >= max && recorded.1 >= max) {
+                sampling.fix_runs = n;
+                sampling.rnd_runs = m;
+                sampling.rounds = rounds;
+                sampling.save(&self.trace_path)?;
+                return Ok(sampling);
+            }
 
-            exec(&self.fix_cmd).unwrap();
+            let step = adaptive.step.max(1);
+            target = ((recorded.0 + step).min(max), (recorded.1 + step).min(max));
+        }
+    }
 
-            let trace = self.collect_trace(acceptor);
+    fn record_range(&self, fix: Range<usize>, rnd: Range<usize>, seed: Option<u64>) -> Result<()> {
+        let later = fix.start > 0 || rnd.start > 0;
+        let mut schedule = Schedule::new(self.schedule, fix, rnd, seed);
+
+        let saved = Schedule::load(&self.trace_path)
+            .ok()
+            .filter(|saved| saved.mode == schedule.mode);
+
+        let saved = match saved {
+            // a resumed campaign keeps its recorded order
+            Some(saved) if self.monitor.resume && saved.contains(&schedule) => {
+                log::info!("resume recorded schedule");
+                schedule.order = saved
+                    .order
+                    .iter()
+                    .filter(|run| schedule.order.contains(run))
+                    .cloned()
+                    .collect();
+                saved
+            }
+            // later rounds append to the schedule of earlier ones
+            Some(mut saved) if later => {
+                saved.extend(schedule.clone());
+                saved
+            }
+            _ => schedule.clone(),
+        };
+        log::info!("{:?} schedule, seed: {:?}", saved.mode, saved.seed);
+        saved.save(&self.trace_path)?;
+
+        let runs: Vec<_> = schedule
+            .order
+            .iter()
+            .map(|(stage, idx)| {
+                let cmd = match stage.as_str() {
+                    "fix" => &self.fix_cmd,
+                    _ => &self.rnd_cmd,
+                };
+                Run::new(&self.trace_path, stage, *idx, cmd)
+            })
+            .collect();
+
+        record_runs(&runs, &self.monitor)?;
+        Ok(())
+    }
 
-            evidence.merge_trace(trace);
+    /// Remove runs left under `trace_path` by an earlier campaign recording more runs than
+    /// `fix`, `rnd` and `calib`, they would be loaded along with the runs of this one
+    fn remove_stale(&self, fix: usize, rnd: usize, calib: usize) -> Result<()> {
+        for (stage, times) in [("fix", fix), ("rnd", rnd), ("calib", calib)] {
+            let path = format!("{}/{stage}", self.trace_path);
+            for idx in indexed_dirs(&path).into_iter().filter(|idx| *idx >= times) {
+                log::info!("remove stale run {stage}/{idx}");
+                std::fs::remove_dir_all(format!("{path}/{idx}"))?;
+            }
         }
+        Ok(())
+    }
 
-        evidence
+    fn runs(&self, stage: &str, cmd: &str, times: usize) -> Vec<Run> {
+        (0..times)
+            .map(|idx| Run::new(&self.trace_path, stage, idx, cmd))
+            .collect()
     }
 
-    pub fn run_rnd(&mut self) -> Evidence {
-        log::info!("run {} times", self.times);
+    /// Load every recorded execution of `stage` (`fix` or `rnd`) under `trace_path`
+    /// without executing the command, return the evidence and the number of merged runs.
+    /// A run whose trace can't be loaded is skipped unless `abort_on_bad_run` is set
+    pub fn load(&mut self, stage: &str) -> Result<(Evidence, usize)> {
+        let traces = load_runs(
+            &self.trace_path,
+            stage,
+            &self.granularity,
+            &self.coalescing,
+            self.abort_on_bad_run,
+        )?;
 
         let mut evidence = Evidence::default();
+        let merged = traces.len();
+        for (mut trace, names) in traces {
+            for (ty, name) in names {
+                self.kernels.entry(ty).or_insert_with(|| Rc::new(name));
+            }
+            for (id, buffer) in std::mem::take(&mut trace.buffers) {
+                self.buffers.entry(id).or_insert(buffer);
+            }
+            evidence.merge_trace(trace)?;
+        }
 
-        for idx in 0..self.times {
-            println!("-------------- {}/{} --------------", idx + 1, self.times);
+        Ok((evidence, merged))
+    }
 
-            let acceptor = prepare(&self.trace_path, "rnd", idx);
+    pub fn test(&mut self) -> Result<Report> {
+        // record traces of fix and random command
+        log::info!("record fix and rand input traces");
+        self.record()?;
 
-            exec(&self.rnd_cmd).unwrap();
+        self.analyze()
+    }
 
-            // let trace: Trace = acceptor.raw_trace().into();
-            let trace = self.collect_trace(acceptor);
+    /// Offline analysis on traces recorded by previous executions
+    pub fn analyze(&mut self) -> Result<Report> {
+        log::info!("load fix input traces");
+        let (fix, n) = self.load("fix")?;
 
-            evidence.merge_trace(trace);
-        }
+        log::info!("load rand input traces");
+        let (rnd, m) = self.load("rnd")?;
 
-        evidence
+        let mut report = self.report(fix, rnd, n, m)?;
+        report.sampling = Sampling::load(&self.trace_path).ok();
+        Ok(report)
     }
 
-    fn collect_trace(&mut self, acceptor: DataAcceptor) -> Trace {
-        let raw_trace = acceptor.raw_trace();
-        raw_trace.kernels.iter().for_each(|k| {
-            if !self.kernels.contains_key(&k.ty) {
-                self.kernels.insert(k.ty, Rc::new(k.name.clone()));
-            }
-        });
+    /// Test the kernels passing `filter` by `method`, p values are replaced by empirical ones
+    /// if `permutation` is set and then adjusted by `correction`.
+    /// Sites differing between fixed input runs are marked or dropped by `calibration`
+    fn test_evidence(
+        &mut self,
+        fix: Evidence,
+        rnd: Evidence,
+        n: usize,
+        m: usize,
+    ) -> Result<TestResult> {
+        let excluded: HashSet<_> = self
+            .kernels
+            .iter()
+            .filter(|(_, name)| !self.filter.is_tested(name))
+            .map(|(ty, _)| *ty)
+            .collect();
+        let method = self.method;
+
+        let mut res = test_kernels(fix, rnd, n, m, &excluded, method)?;
+
+        if let Some(permutation) = self.permutation.as_mut() {
+            // the seed is kept for the report
+            permutation.seed.get_or_insert_with(rng::Rng::seed);
+
+            let (trace_path, abort_on_bad_run) = (self.trace_path.as_str(), self.abort_on_bad_run);
+            let (granularity, coalescing) = (self.granularity, self.coalescing);
+            let load = |stage| -> Result<Vec<Trace>> {
+                let runs = load_runs(
+                    trace_path,
+                    stage,
+                    &granularity,
+                    &coalescing,
+                    abort_on_bad_run,
+                )?;
+                Ok(runs.into_iter().map(|(trace, _)| trace).collect())
+            };
+            permutation.apply(
+                &mut res,
+                || Ok((load("fix")?, load("rnd")?)),
+                |fix, rnd, n, m| test_kernels(fix, rnd, n, m, &excluded, method),
+            )?;
+        }
+
+        res.correct(self.correction);
 
-        raw_trace.into()
+        if self.calibration != Calibration::None {
+            self.baseline(&excluded)?.apply(&mut res, self.calibration);
+        }
+        Ok(res)
     }
 
-    pub fn test(&mut self) -> Report {
-        // collect traces of fix command
-        log::info!("collect fix input traces");
-        let fix = self.run_fix();
+    /// Test fixed input runs against each other, the runs of `trace_path/calib` if recorded,
+    /// else alternate halves of the fixed input runs
+    fn baseline(&self, excluded: &HashSet<KernelTy>) -> Result<Baseline> {
+        let load = |stage| -> Result<Vec<Trace>> {
+            let runs = load_runs(
+                &self.trace_path,
+                stage,
+                &self.granularity,
+                &self.coalescing,
+                self.abort_on_bad_run,
+            )?;
+            Ok(runs.into_iter().map(|(trace, _)| trace).collect())
+        };
+
+        let fix = load("fix")?;
+        let calib = load("calib")?;
+        let (l, r) = if calib.is_empty() {
+            let (l, r): (Vec<_>, Vec<_>) =
+                fix.into_iter().enumerate().partition(|(i, _)| i % 2 == 0);
+            (
+                l.into_iter().map(|(_, trace)| trace).collect(),
+                r.into_iter().map(|(_, trace)| trace).collect(),
+            )
+        } else {
+            (fix, calib)
+        };
+
+        if l.is_empty() || r.is_empty() {
+            log::warn!("calibration needs two fixed input runs");
+            return Ok(Baseline::default());
+        }
 
-        // collect traces of random command
-        log::info!("collect rand input traces");
-        let rnd = self.run_rnd();
+        let (n, m) = (l.len(), r.len());
+        let merge = |runs: Vec<Trace>| -> Result<Evidence> {
+            let mut evidence = Evidence::default();
+            for trace in runs {
+                evidence.merge_trace(trace)?;
+            }
+            Ok(evidence)
+        };
+
+        let mut res = test_kernels(merge(l)?, merge(r)?, n, m, excluded, self.method)?;
+        res.correct(self.correction);
+
+        let baseline = Baseline::new(&res, self.threshold);
+        log::info!(
+            "{n} vs {m} fixed input runs, {} sites differ under identical inputs",
+            baseline.len()
+        );
+        Ok(baseline)
+    }
 
+    fn report(&mut self, fix: Evidence, rnd: Evidence, n: usize, m: usize) -> Result<Report> {
         log::info!("Testing");
-        let dc_res = DeviceTest::test(fix, rnd, self.times, self.times, self.threshold);
+        let dc_res = self.test_evidence(fix, rnd, n, m)?;
         log::info!("Test finished");
+
+        let coverage = Coverage::new(&dc_res, self.threshold);
+        log::info!(
+            "tested {} kernel calls, {} nodes, {} instructions, {} failed, {} untestable",
+            coverage.calls.tested,
+            coverage.nodes.tested,
+            coverage.instrs.tested,
+            coverage.calls.failed,
+            coverage.calls.untestable
+        );
         // log::debug!("{:?}", dc_res);
 
         log::info!("Generating report");
-        let mut builder = Report::builder(std::mem::take(&mut self.kernels));
+        let mut builder = Report::builder(std::mem::take(&mut self.kernels), self.threshold)
+            .buffers(std::mem::take(&mut self.buffers));
 
-        dc_res.diff_kernel.into_iter().for_each(|res| {
-            builder.add_diff_kernel(res);
-        });
+        for res in dc_res.diff_kernel {
+            builder.add_diff_kernel(res)?;
+        }
 
-        dc_res.eq_kernel.into_iter().for_each(|res| {
-            builder.add_eq_kernel(res);
-        });
+        for res in dc_res.eq_kernel {
+            builder.add_eq_kernel(res)?;
+        }
 
-        builder.build()
+        let mut report = builder.build();
+        report.correction = self.correction;
+        report.method = self.method;
+        report.permutation = self.permutation;
+        report.calibration = self.calibration;
+        report.coverage = coverage;
+        report.granularity = self.granularity;
+        report.coalescing = self.coalescing;
+        Ok(report)
     }
 }
 
-fn exec(cmd: &str) -> Result<(), ()> {
-    log::debug!("execute test program");
-
-    let mut p = std::process::Command::new("sh");
-    p.arg("-c");
-    p.arg(cmd);
-    let stdout = p
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("failed to execute child")
-        .stdout
-        .unwrap();
+/// Test the kernels of both sides except the `excluded` ones by `method`
+fn test_kernels(
+    mut fix: Evidence,
+    mut rnd: Evidence,
+    n: usize,
+    m: usize,
+    excluded: &HashSet<KernelTy>,
+    method: TestMethod,
+) -> Result<TestResult> {
+    if !excluded.is_empty() {
+        fix.retain_kernels(|ty| !excluded.contains(&ty));
+        rnd.retain_kernels(|ty| !excluded.contains(&ty));
+    }
+
+    DeviceTest::test(fix, rnd, n, m, &method)
+}
+
+/// Trace of a recorded run and the names of the kernels it launches
+type RunTrace = (Trace, Vec<(KernelTy, String)>);
+
+/// Load the trace of every recorded run of `stage` under `trace_path`, each with the names of
+/// the kernels it launches, addresses are bucketed by `granularity` and global memory
+/// transactions counted by `coalescing`.
+/// A run whose trace can't be loaded is skipped unless `abort_on_bad_run` is set
+fn load_runs(
+    trace_path: &str,
+    stage: &str,
+    granularity: &Granularity,
+    coalescing: &Coalescing,
+    abort_on_bad_run: bool,
+) -> Result<Vec<RunTrace>> {
+    let mut runs = indexed_dirs(&format!("{trace_path}/{stage}"));
+
+    // traces of crashed runs are never merged
+    runs.retain(|idx| {
+        let mergeable = is_mergeable(&format!("{trace_path}/{stage}/{idx}"));
+        if !mergeable {
+            log::warn!("skip failed run {stage}/{idx}");
+        }
+        mergeable
+    });
+    log::info!("load {} recorded {stage} traces", runs.len());
 
-    let reader = BufReader::new(stdout);
+    let mut traces = Vec::new();
+    for idx in runs.iter() {
+        let acceptor = DataAcceptor::new(format!("{trace_path}/{stage}/{idx}/"));
 
-    reader
-        .lines()
-        .filter_map(|line| line.ok())
-        .for_each(|line| println!("{}", line));
+        let trace = acceptor.raw_trace().map_err(Error::from).and_then(|raw| {
+            let names = raw.kernels.iter().map(|k| (k.ty, k.name.clone())).collect();
+            Ok((Trace::from_raw(raw, granularity, coalescing)?, names))
+        });
+        match trace {
+            Ok(trace) => traces.push(trace),
+            Err(e) if !abort_on_bad_run => {
+                log::warn!("skip bad run {stage}/{idx}: {e}");
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(traces)
+}
+
+/// Collect sorted indices of the numeric sub directories in `path`
+pub fn indexed_dirs(path: &str) -> Vec<usize> {
+    let mut indices: Vec<_> = std::fs::read_dir(path)
+        .map(|dir| {
+            dir.filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().to_str()?.parse::<usize>().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    indices.sort();
+    indices
+}
 
-    Ok(())
+/// Record a single trace of every command, `cmds[idx]` is recorded to `root_path/<idx>/fix/0`
+pub fn record_traces(
+    cmds: &[String],
+    root_path: &str,
+    monitor: &Monitor,
+) -> std::io::Result<Vec<RunStatus>> {
+    let runs: Vec<_> = cmds
+        .iter()
+        .enumerate()
+        .map(|(idx, cmd)| Run::new(&format!("{root_path}/{idx}"), "fix", 0, cmd))
+        .collect();
+
+    record_runs(&runs, monitor)
 }
 
-fn prepare(root_path: &str, stage: &str, idx: usize) -> DataAcceptor {
-    let trace_path = format!("{root_path}/{stage}/{idx}/");
-    log::info!("Recorded trace path: {}", trace_path);
-    std::fs::create_dir_all(&trace_path).unwrap();
+/// Load the trace saved by [`record_traces`], None if the run failed
+pub fn load_trace(root_path: &str) -> Result<Option<Trace>> {
+    let trace_path = format!("{root_path}/fix/0/");
+    if !is_mergeable(&trace_path) {
+        return Ok(None);
+    }
+
+    Ok(Some(DataAcceptor::new(trace_path).raw_trace()?.try_into()?))
+}
 
-    std::env::set_var("OWL_TRACE", &trace_path);
+pub fn get_trace(cmd: &str, root_path: &str) -> Result<Option<Trace>> {
+    record_runs(&[Run::new(root_path, "fix", 0, cmd)], &Monitor::default())?;
 
-    DataAcceptor::new(trace_path)
+    load_trace(root_path)
 }
 
-pub fn get_trace(cmd: &str, root_path: &str) -> Trace {
-    let acceptor = prepare(root_path, "fix", 0);
-    exec(cmd).unwrap();
+#[cfg(test)]
+mod test {
+    use super::{indexed_dirs, load_trace, Adaptive, Analyzer, Error, KernelFilter, Sampling};
+    use crate::fixture::{Kernel, TempDir};
 
-    acceptor.raw_trace().into()
+    #[test]
+    pub fn test_offline_analyze() {
+        let dir = TempDir::new("offline");
+        let root = dir.path();
+
+        for idx in 0..3 {
+            Kernel::new().node(1).write(root, "fix", idx);
+            Kernel::new().node(2).write(root, "rnd", idx);
+        }
+        std::fs::create_dir_all(dir.join("fix/not_a_run")).unwrap();
+
+        assert_eq!(indexed_dirs(&dir.join("fix")), vec![0, 1, 2]);
+        assert!(indexed_dirs(&dir.join("missing")).is_empty());
+
+        // crashed run and run without trace are not merged
+        Kernel::new().node(2).write(root, "fix", 3);
+        std::fs::write(
+            dir.join("fix/3/status.json"),
+            r#"{"cmd":"","attempts":1,"exit_code":null,"signal":9,"timed_out":false,"traced":true,"elapsed_ms":1,"error":null}"#,
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.join("fix/4")).unwrap();
+
+        let mut analyzer = Analyzer {
+            trace_path: root.to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(analyzer.load("fix").unwrap().1, 3);
+        let report = analyzer.analyze().unwrap();
+
+        assert!(report.kernel_leak.is_empty());
+        assert_eq!(report.cf_leak.values().map(|s| s.len()).sum::<usize>(), 1);
+        assert_eq!(report.coverage.calls.tested, 1);
+        assert_eq!(
+            (report.coverage.nodes.tested, report.coverage.nodes.failed),
+            (1, 1)
+        );
+
+        let csv = report.to_csv();
+        assert_eq!(csv.lines().count(), 2);
+        assert!(csv.lines().nth(1).unwrap().starts_with("cf,k,"));
+
+        // excluded kernels are not tested
+        let mut analyzer = Analyzer {
+            trace_path: root.to_owned(),
+            filter: KernelFilter {
+                exclude: vec!["k".to_owned()],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = analyzer.analyze().unwrap();
+        assert!(report.cf_leak.values().all(|s| s.is_empty()));
+        // no leakage because nothing is tested
+        assert_eq!(report.coverage.calls.tested, 0);
+
+        // a run with a bad trace is skipped, or fails loading if asked to
+        std::fs::create_dir_all(dir.join("fix/5")).unwrap();
+        std::fs::write(
+            dir.join("fix/5/kernel.json"),
+            r#"{"type":"Alloc","data":[]}"#,
+        )
+        .unwrap();
+        let mut analyzer = Analyzer {
+            trace_path: root.to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(analyzer.load("fix").unwrap().1, 3);
+        analyzer.abort_on_bad_run = true;
+        assert!(matches!(
+            analyzer.load("fix"),
+            Err(Error::Trace(monitor::Error::Schema { .. }))
+        ));
+
+        // every node of a kernel is dumped once
+        let mut dup: serde_json::Value =
+            serde_json::from_str(&Kernel::new().node(1).json()).unwrap();
+        let nodes = dup["data"][0]["g"]["nodes"].as_array_mut().unwrap();
+        nodes.push(nodes[0].clone());
+        std::fs::write(dir.join("fix/5/kernel.json"), dup.to_string()).unwrap();
+        assert!(matches!(analyzer.load("fix"), Err(Error::Schema(_))));
+    }
+
+    #[test]
+    pub fn test_record_asymmetric() {
+        let root = TempDir::new("asymmetric");
+
+        let mut analyzer = Analyzer {
+            fix_cmd: Kernel::new().node(1).stub(),
+            rnd_cmd: Kernel::new().node(2).stub(),
+            fix_times: 2,
+            rnd_times: 5,
+            trace_path: root.path().to_owned(),
+            ..Default::default()
+        };
+        analyzer.record().unwrap();
+
+        assert_eq!(analyzer.load("fix").unwrap().1, 2);
+        assert_eq!(analyzer.load("rnd").unwrap().1, 5);
+        let report = analyzer.analyze().unwrap();
+        assert_eq!(report.cf_leak.values().map(|s| s.len()).sum::<usize>(), 1);
+        // the kernel is called in every run of both sides, 2 and 5 runs are not a difference
+        assert!(report.kernel_leak.is_empty());
+    }
+
+    #[test]
+    pub fn test_stale_runs() {
+        let root = TempDir::new("stale");
+
+        let mut analyzer = Analyzer {
+            fix_cmd: Kernel::new().node(1).stub(),
+            rnd_cmd: Kernel::new().node(1).stub(),
+            fix_times: 2,
+            rnd_times: 2,
+            threshold: 0.2,
+            trace_path: root.path().to_owned(),
+            ..Default::default()
+        };
+        analyzer.record_adaptive(&Adaptive::default()).unwrap();
+        analyzer.fix_times = 4;
+        analyzer.rnd_times = 4;
+        analyzer.calibration_times = 3;
+        analyzer.record().unwrap();
+        assert!(Sampling::load(root.path()).is_err());
+
+        // a smaller campaign in the same directory only loads its own runs
+        analyzer.fix_times = 3;
+        analyzer.rnd_times = 2;
+        analyzer.calibration_times = 0;
+        analyzer.record().unwrap();
+        assert_eq!(analyzer.load("fix").unwrap().1, 3);
+        assert_eq!(analyzer.load("rnd").unwrap().1, 2);
+        assert!(indexed_dirs(&root.join("calib")).is_empty());
+    }
+
+    #[test]
+    pub fn test_unreadable_allocs() {
+        let root = TempDir::new("allocs");
+        let buffer = |file: Option<&str>| {
+            Kernel::new()
+                .pool(0x10000, 4096)
+                .alloc(0x10000, 4096, &[("main", "aes.cu", 120)])
+                .node(1)
+                .write(root.path(), "fix", 0);
+            if let Some(file) = file {
+                std::fs::write(root.join(&format!("fix/0/{file}")), "{").unwrap();
+            }
+            let trace = load_trace(root.path()).unwrap().unwrap();
+            trace.buffers.values().next().unwrap().to_string()
+        };
+
+        assert_eq!(buffer(None), "buffer allocated at aes.cu:120 (4 KiB)");
+        // allocations and contexts only lose the attribution
+        assert_eq!(
+            buffer(Some("context.json")),
+            "buffer allocated by cudaMalloc (4 KiB)"
+        );
+        assert_eq!(buffer(Some("alloc.json")), "buffer 0 (4 KiB)");
+    }
 }
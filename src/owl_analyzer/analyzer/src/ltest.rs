@@ -0,0 +1,523 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    contingency::Contingency,
+    hist::{chi2_p_value, ks_test_p_value},
+    observation::Observations,
+};
+
+/// One side of a compared site
+pub struct Sample<'a> {
+    /// Counts over the bins shared by both sides, pooled over every run
+    pub hist: &'a [f64],
+    /// One observation per run
+    pub runs: &'a Observations,
+}
+
+impl Sample<'_> {
+    fn total(&self) -> f64 {
+        self.hist.iter().sum()
+    }
+
+    /// Counts scaled to one observation per run, executions within a run aren't independent
+    /// so the sample size is the number of runs. Without runs the pooled counts are kept
+    fn per_run(&self) -> Vec<f64> {
+        let total = self.total();
+        if total == 0.0 || self.runs.is_empty() {
+            return self.hist.to_vec();
+        }
+
+        let scale = self.runs.len() as f64 / total;
+        self.hist.iter().map(|c| c * scale).collect()
+    }
+}
+
+/// p value and statistic of a compared site
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Outcome {
+    /// p value of both sides being drawn from the same distribution
+    pub p: f64,
+    /// Statistic of the test the p value is taken from
+    pub statistic: f64,
+}
+
+impl Outcome {
+    pub fn new(p: f64, statistic: f64) -> Self {
+        Self { p, statistic }
+    }
+}
+
+/// Statistical test comparing the fixed and random input side of a site
+pub trait LeakageTest {
+    fn test(&self, l: &Sample, r: &Sample) -> Outcome;
+}
+
+/// Size of the difference of both sides of a site, unlike the p value it doesn't grow with the
+/// number of observations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Effect {
+    /// Statistic of the test method
+    pub statistic: f64,
+    /// Largest distance of the cumulative distributions, the KS statistic
+    pub d: f64,
+    /// Cramér's V of the contingency table
+    pub cramers_v: f64,
+    /// Total variation distance of the normalized histograms
+    pub tvd: f64,
+    /// Observations of the fixed and random side, pooled over every run
+    pub fix_obs: usize,
+    pub rnd_obs: usize,
+}
+
+impl Effect {
+    /// Effect sizes of the pooled histograms, a site reached on one side only is at the
+    /// largest distance
+    pub fn new(l: &Sample, r: &Sample, statistic: f64) -> Self {
+        let (l_sum, r_sum) = (l.total(), r.total());
+        let mut effect = Self {
+            statistic,
+            fix_obs: l_sum as usize,
+            rnd_obs: r_sum as usize,
+            ..Default::default()
+        };
+
+        match (l_sum == 0.0, r_sum == 0.0) {
+            (true, true) => {}
+            (false, false) => {
+                let (mut l_cdf, mut r_cdf) = (0.0, 0.0);
+                for (lc, rc) in l.hist.iter().zip(r.hist.iter()) {
+                    let (lp, rp) = (lc / l_sum, rc / r_sum);
+                    l_cdf += lp;
+                    r_cdf += rp;
+                    effect.d = effect.d.max((l_cdf - r_cdf).abs());
+                    effect.tvd += (lp - rp).abs() / 2.0;
+                }
+                effect.cramers_v = Contingency::new(l.hist, r.hist).cramers_v();
+            }
+            _ => {
+                effect.d = 1.0;
+                effect.cramers_v = 1.0;
+                effect.tvd = 1.0;
+            }
+        }
+
+        effect
+    }
+}
+
+/// Test applied to every control flow and data flow site of an analysis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TestMethod {
+    /// Kolmogorov-Smirnov on the pooled histograms
+    #[default]
+    Ks,
+    /// Chi-squared test of homogeneity, Fisher exact test for small counts
+    Chi2,
+    /// Likelihood-ratio G-test of homogeneity
+    G,
+    /// Anderson-Darling two-sample test
+    Ad,
+    /// Welch's t-test on the per-run observations
+    Welch,
+}
+
+impl FromStr for TestMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ks" => Ok(Self::Ks),
+            "chi2" => Ok(Self::Chi2),
+            "g" => Ok(Self::G),
+            "ad" => Ok(Self::Ad),
+            "welch" => Ok(Self::Welch),
+            _ => Err(format!(
+                "unknown test `{s}`, expect ks, chi2, g, ad or welch"
+            )),
+        }
+    }
+}
+
+impl LeakageTest for TestMethod {
+    fn test(&self, l: &Sample, r: &Sample) -> Outcome {
+        match self {
+            TestMethod::Ks => Ks.test(l, r),
+            TestMethod::Chi2 => ChiSquared.test(l, r),
+            TestMethod::G => GTest.test(l, r),
+            TestMethod::Ad => AndersonDarling.test(l, r),
+            TestMethod::Welch => Welch.test(l, r),
+        }
+    }
+}
+
+/// A site reached on one side only is the most extreme outcome of the run counts, its
+/// statistic is the KS distance. None if both sides have to be compared
+fn reached_once(l: &Sample, r: &Sample) -> Option<Outcome> {
+    match (l.total() == 0.0, r.total() == 0.0) {
+        (true, true) => Some(Outcome::new(1.0, 0.0)),
+        (false, false) => None,
+        _ => Some(Outcome::new(
+            ks_test_p_value(1.0, l.runs.len(), r.runs.len()),
+            1.0,
+        )),
+    }
+}
+
+pub struct Ks;
+
+impl LeakageTest for Ks {
+    fn test(&self, l: &Sample, r: &Sample) -> Outcome {
+        if let Some(outcome) = reached_once(l, r) {
+            return outcome;
+        }
+
+        let (l_sum, r_sum) = (l.total(), r.total());
+        let (mut l_cdf, mut r_cdf) = (0.0, 0.0);
+        let mut max_diff = 0f64;
+        for (lc, rc) in l.hist.iter().zip(r.hist.iter()) {
+            l_cdf += lc / l_sum;
+            r_cdf += rc / r_sum;
+            max_diff = max_diff.max((l_cdf - r_cdf).abs());
+        }
+
+        Outcome::new(
+            ks_test_p_value(max_diff, l.runs.len(), r.runs.len()),
+            max_diff,
+        )
+    }
+}
+
+/// Chi-squared test of the contingency table, Fisher exact test for small counts
+pub struct ChiSquared;
+
+impl LeakageTest for ChiSquared {
+    fn test(&self, l: &Sample, r: &Sample) -> Outcome {
+        if let Some(outcome) = reached_once(l, r) {
+            return outcome;
+        }
+
+        let (lh, rh) = (l.per_run(), r.per_run());
+        let table = Contingency::new(&lh, &rh);
+        let (stat, _) = table.statistic(|o, e| (o - e).powi(2) / e);
+        Outcome::new(table.p_value(), stat)
+    }
+}
+
+pub struct GTest;
+
+impl LeakageTest for GTest {
+    fn test(&self, l: &Sample, r: &Sample) -> Outcome {
+        if let Some(outcome) = reached_once(l, r) {
+            return outcome;
+        }
+
+        let (lh, rh) = (l.per_run(), r.per_run());
+        let (stat, df) = Contingency::new(&lh, &rh).statistic(|o, e| {
+            if o == 0.0 {
+                0.0
+            } else {
+                2.0 * o * (o / e).ln()
+            }
+        });
+        if df == 0 {
+            return Outcome::new(1.0, stat);
+        }
+        Outcome::new(chi2_p_value(stat, df as f64), stat)
+    }
+}
+
+/// Anderson-Darling k-sample test of Scholz and Stephens for two samples,
+/// bins are ordered values with ties
+pub struct AndersonDarling;
+
+/// Quadratic fit of `ln(p)` on the standardized statistic, through the critical values of
+/// Scholz and Stephens for one degree of freedom
+const AD_FIT: [f64; 3] = [
+    -1.0910254865440174,
+    -0.9926908248846223,
+    0.015327229656736083,
+];
+
+/// Largest total count whose variance terms are summed exactly
+const AD_EXACT_LIMIT: f64 = 1e6;
+
+impl LeakageTest for AndersonDarling {
+    fn test(&self, l: &Sample, r: &Sample) -> Outcome {
+        if let Some(outcome) = reached_once(l, r) {
+            return outcome;
+        }
+
+        let k = 2.0;
+        let (lh, rh) = (l.per_run(), r.per_run());
+        let sizes: [f64; 2] = [lh.iter().sum(), rh.iter().sum()];
+        let n = sizes[0] + sizes[1];
+        if n < 4.0 {
+            return Outcome::new(1.0, 0.0);
+        }
+
+        // midrank statistic A2akN, allowing ties
+        let mut a2 = 0.0;
+        let mut before = 0.0;
+        let mut before_i = [0.0; 2];
+        for (lc, rc) in lh.iter().zip(rh.iter()) {
+            let tie = lc + rc;
+            if tie == 0.0 {
+                continue;
+            }
+
+            let b = before + tie / 2.0;
+            let denom = b * (n - b) - n * tie / 4.0;
+            for (i, count) in [*lc, *rc].into_iter().enumerate() {
+                let m = before_i[i] + count / 2.0;
+                if denom > 0.0 {
+                    a2 += tie / n * (n * m - sizes[i] * b).powi(2) / denom / sizes[i];
+                }
+                before_i[i] += count;
+            }
+            before += tie;
+        }
+        a2 *= (n - 1.0) / n;
+
+        // variance of A2akN under the null hypothesis
+        let h_sum: f64 = sizes.iter().map(|s| 1.0 / s).sum();
+        let (h, g) = if n <= AD_EXACT_LIMIT {
+            let total = n as usize;
+            // harmonic[i] = sum of 1 / j for j in 1..=i
+            let mut harmonic = vec![0.0; total];
+            for i in 1..total {
+                harmonic[i] = harmonic[i - 1] + 1.0 / i as f64;
+            }
+            let h = harmonic[total - 1];
+            let g: f64 = (1..total - 1)
+                .map(|i| (h - harmonic[i]) / (n - i as f64))
+                .sum();
+            (h, g)
+        } else {
+            // h approaches ln(N) + euler gamma, g approaches pi^2 / 6
+            (
+                (n - 1.0).ln() + 0.577_215_664_901_532_9,
+                std::f64::consts::PI.powi(2) / 6.0,
+            )
+        };
+
+        let a = (4.0 * g - 6.0) * (k - 1.0) + (10.0 - 6.0 * g) * h_sum;
+        let b = (2.0 * g - 4.0) * k * k + 8.0 * h * k + (2.0 * g - 14.0 * h - 4.0) * h_sum
+            - 8.0 * h
+            + 4.0 * g
+            - 6.0;
+        let c = (6.0 * h + 2.0 * g - 2.0) * k * k
+            + (4.0 * h - 4.0 * g + 6.0) * k
+            + (2.0 * h - 6.0) * h_sum
+            + 4.0 * h;
+        let d = (2.0 * h + 6.0) * k * k - 4.0 * h * k;
+        let var = (a * n.powi(3) + b * n * n + c * n + d) / ((n - 1.0) * (n - 2.0) * (n - 3.0));
+
+        // the fit turns back up at its vertex
+        let t = ((a2 - (k - 1.0)) / var.sqrt()).min(-AD_FIT[1] / (2.0 * AD_FIT[2]));
+        let p = (AD_FIT[0] + AD_FIT[1] * t + AD_FIT[2] * t * t)
+            .exp()
+            .clamp(0.0, 1.0);
+        Outcome::new(p, a2)
+    }
+}
+
+pub struct Welch;
+
+impl LeakageTest for Welch {
+    fn test(&self, l: &Sample, r: &Sample) -> Outcome {
+        let (t, p) = l.runs.welch_test(r.runs);
+        Outcome::new(p, t)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::observation::Observations;
+
+    use super::{Effect, LeakageTest, Sample, TestMethod};
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    /// p value of `method` with one run per execution of the larger side
+    fn p(method: TestMethod, l: &[f64], r: &[f64]) -> f64 {
+        let n = l.iter().sum::<f64>().max(r.iter().sum());
+        let runs = Observations::default().padded(n as usize);
+        method
+            .test(
+                &Sample {
+                    hist: l,
+                    runs: &runs,
+                },
+                &Sample {
+                    hist: r,
+                    runs: &runs,
+                },
+            )
+            .p
+    }
+
+    #[test]
+    pub fn test_homogeneity() {
+        // 2 x 2 table [[10, 20], [20, 10]]
+        assert_close(
+            p(TestMethod::Chi2, &[10.0, 20.0], &[20.0, 10.0]),
+            0.009823274507519245,
+        );
+        assert_close(
+            p(TestMethod::G, &[10.0, 20.0], &[20.0, 10.0]),
+            0.009136430620696662,
+        );
+
+        // empty bins are dropped
+        assert_close(
+            p(TestMethod::Chi2, &[10.0, 0.0, 20.0], &[20.0, 0.0, 10.0]),
+            0.009823274507519245,
+        );
+        assert_close(p(TestMethod::Chi2, &[5.0, 5.0], &[10.0, 10.0]), 1.0);
+        assert_close(p(TestMethod::G, &[4.0, 0.0], &[7.0, 0.0]), 1.0);
+    }
+
+    #[test]
+    pub fn test_anderson_darling() {
+        assert_close(
+            p(TestMethod::Ad, &[5.0, 3.0, 2.0], &[1.0, 3.0, 6.0]),
+            0.009299319204821632,
+        );
+        assert_close(
+            p(TestMethod::Ad, &[5.0, 5.0, 0.0, 2.0], &[0.0, 4.0, 4.0, 4.0]),
+            0.0016167604572383126,
+        );
+        assert_close(p(TestMethod::Ad, &[3.0; 4], &[3.0; 4]), 1.0);
+    }
+
+    #[test]
+    pub fn test_methods() {
+        for method in [
+            TestMethod::Ks,
+            TestMethod::Chi2,
+            TestMethod::G,
+            TestMethod::Ad,
+        ] {
+            // site reached on one side only
+            assert_close(
+                p(method, &[0.0, 0.0], &[3.0, 1.0]),
+                p(TestMethod::Ks, &[0.0], &[4.0]),
+            );
+            assert_eq!(p(method, &[0.0], &[0.0]), 1.0);
+            assert!(p(method, &[40.0, 0.0], &[0.0, 40.0]) < 1e-4);
+        }
+
+        // welch only looks at the runs
+        let l = Observations::single(1.0).padded(3);
+        let r = Observations::single(1.0).padded(3);
+        let hist = [1.0, 0.0];
+        let sample = |runs| Sample { hist: &hist, runs };
+        assert_eq!(TestMethod::Welch.test(&sample(&l), &sample(&r)).p, 1.0);
+
+        // 2 and 5 runs taking another branch 4 times each
+        let runs = |n| {
+            (1..n).fold(Observations::single(4.0), |mut runs, _| {
+                runs.merge(Observations::single(4.0));
+                runs
+            })
+        };
+        let (l, r) = (runs(2), runs(5));
+        let (lh, rh) = ([8.0, 0.0], [0.0, 20.0]);
+        let (l, r) = (
+            Sample {
+                hist: &lh,
+                runs: &l,
+            },
+            Sample {
+                hist: &rh,
+                runs: &r,
+            },
+        );
+        for method in [
+            TestMethod::Ks,
+            TestMethod::Chi2,
+            TestMethod::G,
+            TestMethod::Ad,
+        ] {
+            assert!(method.test(&l, &r).p < 0.1, "{method:?}");
+        }
+        assert_eq!(TestMethod::Welch.test(&l, &r).p, 1.0);
+
+        assert_eq!("ad".parse::<TestMethod>(), Ok(TestMethod::Ad));
+        assert!("t".parse::<TestMethod>().is_err());
+    }
+
+    #[test]
+    pub fn test_run_sample_size() {
+        // 10 runs on both sides, executing the site 3 and 30 times per run
+        let runs = Observations::default().padded(10);
+        let sample = |hist| Sample { hist, runs: &runs };
+        let (l, r) = ([10.0, 20.0], [20.0, 10.0]);
+        let (l10, r10) = ([100.0, 200.0], [200.0, 100.0]);
+
+        for method in [
+            TestMethod::Ks,
+            TestMethod::Chi2,
+            TestMethod::G,
+            TestMethod::Ad,
+        ] {
+            // the same runs are as much evidence
+            assert_close(
+                method.test(&sample(&l10), &sample(&r10)).p,
+                method.test(&sample(&l), &sample(&r)).p,
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_effect() {
+        let runs = Observations::default().padded(30);
+        let sample = |hist| Sample { hist, runs: &runs };
+        let (l, r) = ([10.0, 20.0], [20.0, 10.0]);
+
+        let outcome = TestMethod::Chi2.test(&sample(&l), &sample(&r));
+        assert_close(outcome.statistic, 20.0 / 3.0);
+        assert_close(
+            TestMethod::Ks.test(&sample(&l), &sample(&r)).statistic,
+            1.0 / 3.0,
+        );
+
+        let effect = Effect::new(&sample(&l), &sample(&r), outcome.statistic);
+        assert_close(effect.d, 1.0 / 3.0);
+        assert_close(effect.tvd, 1.0 / 3.0);
+        assert_close(effect.cramers_v, 1.0 / 3.0);
+        assert_eq!((effect.fix_obs, effect.rnd_obs), (30, 30));
+
+        // the same shape over ten times the runs is a smaller p value, not a larger effect
+        let runs10 = Observations::default().padded(300);
+        let sample10 = |hist| Sample {
+            hist,
+            runs: &runs10,
+        };
+        let (l10, r10) = ([100.0, 200.0], [200.0, 100.0]);
+        let outcome10 = TestMethod::Chi2.test(&sample10(&l10), &sample10(&r10));
+        assert!(outcome10.p < outcome.p);
+        assert_close(
+            Effect::new(&sample10(&l10), &sample10(&r10), outcome10.statistic).cramers_v,
+            effect.cramers_v,
+        );
+
+        // the transitions don't overlap at all
+        let disjoint = Effect::new(&sample(&[8.0, 0.0]), &sample(&[0.0, 20.0]), 1.0);
+        assert_eq!((disjoint.d, disjoint.tvd), (1.0, 1.0));
+        assert_eq!((disjoint.fix_obs, disjoint.rnd_obs), (8, 20));
+        assert_eq!(disjoint.statistic, 1.0);
+
+        let once = Effect::new(&sample(&[0.0, 0.0]), &sample(&[3.0, 1.0]), 1.0);
+        assert_eq!((once.d, once.tvd, once.cramers_v), (1.0, 1.0, 1.0));
+        assert_eq!(
+            Effect::new(&sample(&[0.0]), &sample(&[0.0]), 0.0),
+            Effect::default()
+        );
+    }
+}
@@ -6,7 +6,6 @@ use std::{
 use monitor::raw::RawCf;
 use ndarray::{Array1, Array2};
 
-use crate::{align::VecAlign, hist::ks_test_p_value};
 // use polars::prelude::*;
 
 #[derive(Debug, Clone, serde::Serialize, PartialEq)]
@@ -37,6 +36,11 @@ impl CfMatrix {
         self.flows.is_empty()
     }
 
+    /// Number of recorded flows, the times the node is executed
+    pub fn total(&self) -> usize {
+        self.flows.values().flat_map(|dst| dst.values()).sum()
+    }
+
     // convert rawcf to flows
     pub fn from_raw(value: Vec<RawCf>) -> Self {
         let mut flows = BTreeMap::new();
@@ -71,127 +75,34 @@ impl CfMatrix {
         }
     }
 
-    pub fn test(&self, other: &Self, n: usize, m: usize) -> f64 {
-        struct SrcFlow<'a, T> {
-            id: &'a isize,
-            inner: &'a T,
-        }
-
-        impl<'a, T> PartialEq for SrcFlow<'a, T> {
-            fn eq(&self, other: &Self) -> bool {
-                *self.id == *other.id
-            }
-        }
-
-        /// calcuate p(src, dst)
-        fn normlize_src_dst(
-            left: &BTreeMap<isize, usize>,
-            right: &BTreeMap<isize, usize>,
-        ) -> Vec<(f64, f64)> {
-            log::debug!("normlize");
-            let l_sum: f64 = left.values().sum::<usize>() as f64;
-            let r_sum: f64 = right.values().sum::<usize>() as f64;
-
-            let l: Vec<_> = left
-                .iter()
-                .map(|(id, inner)| SrcFlow { id, inner })
-                .collect();
-            let r: Vec<_> = right
+    /// Every `(src, dst)` cell of either matrix, in order
+    pub fn cells(&self, other: &Self) -> Vec<(isize, isize)> {
+        let cells = |flows: &BTreeMap<isize, BTreeMap<isize, usize>>| {
+            flows
                 .iter()
-                .map(|(id, inner)| SrcFlow { id, inner })
-                .collect();
-
-            let (l, r) = l.align(&r);
-            // let (lh, rh): (Vec<_>, Vec<_>) =
-            l.into_iter()
-                .zip(r.into_iter())
-                .map(|(l, r)| match (l, r) {
-                    (Some(l), Some(r)) => (*l.inner as f64 / l_sum, *r.inner as f64 / r_sum),
-                    (Some(l), None) => (*l.inner as f64 / l_sum, 0.0),
-                    (None, Some(r)) => (0.0, *r.inner as f64 / r_sum),
-                    (None, None) => {
-                        panic!()
-                    }
-                })
-                .collect()
-            // .unzip();
-
-            // let p = ks_test_aligned_norm(&lh, &rh, n, m);
-            // p
-        }
-
-        // let mut l_sum = 0.0;
-        // let mut r_sum = 0.0;
-        let mut lv = Vec::new();
-        let mut rv = Vec::new();
-
-        // if self.flows.is_empty() && other.flows.is_empty() {
-        //     println!("{:?}", self.flows);
-        // }
-        log::debug!("{}, {}", self.flows.len(), other.flows.len());
-
-        // align flow by source
-        let l: Vec<_> = self
-            .flows
-            .iter()
-            .map(|(id, inner)| SrcFlow { id, inner })
-            .collect();
-        let r: Vec<_> = other
-            .flows
-            .iter()
-            .map(|(id, inner)| SrcFlow { id, inner })
-            .collect();
-
-        let (l, r) = l.align(&r);
-
-        l.into_iter().zip(r.into_iter()).for_each(|(l, r)| {
-            match (l, r) {
-                (Some(l), Some(r)) => {
-                    log::debug!("eq src");
-                    let p = normlize_src_dst(l.inner, r.inner);
-                    p.into_iter().for_each(|(l, r)| {
-                        // l_sum += l;
-                        // r_sum += r;
-                        // v.push((l, r));
-                        if l != r {
-                            log::trace!("different cf on node")
-                        }
-                        lv.push(l);
-                        rv.push(r);
-                    });
-                }
-                (Some(_), None) => {
-                    log::trace!("Uneq src, r is none");
-                    lv.push(1.0);
-                    rv.push(0.0);
-                }
-                (None, Some(_)) => {
-                    log::trace!("Uneq src, l is none");
-                    lv.push(0.0);
-                    rv.push(1.0);
-                }
-                (None, None) => {}
-            }
-            // if (l_sum - r_sum).abs() > max_diff {
-            //     max_diff = (l_sum - r_sum).abs()
-            // }
-        });
-
-        log::debug!("calc max diff");
-
-        let mut max_diff = 0.0;
+                .flat_map(|(src, dst)| dst.keys().map(move |dst| (*src, *dst)))
+                .collect::<BTreeSet<_>>()
+        };
 
-        let (l_sum, r_sum): (f64, f64) = (lv.iter().sum(), rv.iter().sum());
+        cells(&self.flows)
+            .union(&cells(&other.flows))
+            .copied()
+            .collect()
+    }
 
-        lv.iter().zip(rv.iter()).for_each(|(l, r)| {
-            if (l / l_sum - r / r_sum).abs() > max_diff {
-                max_diff = (l / l_sum - r / r_sum).abs();
-            }
-        });
+    /// Flow counts of both matrices over the `cells` of both
+    pub fn aligned_counts(&self, other: &Self) -> (Vec<f64>, Vec<f64>) {
+        let count = |flows: &BTreeMap<isize, BTreeMap<isize, usize>>, (src, dst)| {
+            flows
+                .get(&src)
+                .and_then(|d| d.get(&dst))
+                .map_or(0.0, |c| *c as f64)
+        };
 
-        let p = ks_test_p_value(max_diff, n, m);
-        p
-        // .collect();
+        self.cells(other)
+            .into_iter()
+            .map(|cell| (count(&self.flows, cell), count(&other.flows, cell)))
+            .unzip()
     }
 }
 
@@ -257,7 +168,7 @@ mod test {
     use ndarray::Array2;
     use std::collections::BTreeMap;
 
-    use super::{build_matrix_impl, insert_cf_impl};
+    use super::{build_matrix_impl, insert_cf_impl, CfMatrix};
 
     #[test]
     pub fn test_insert_cf() {
@@ -278,4 +189,20 @@ mod test {
             m
         );
     }
+
+    #[test]
+    pub fn test_aligned_counts() {
+        let mut l = CfMatrix::default();
+        l.insert_cf(-1, 3, 2);
+        l.insert_cf(1, 3, 5);
+        let mut r = CfMatrix::default();
+        r.insert_cf(-1, 4, 1);
+
+        // cells (-1, 3), (-1, 4), (1, 3)
+        assert_eq!(
+            l.aligned_counts(&r),
+            (vec![2.0, 0.0, 5.0], vec![0.0, 1.0, 0.0])
+        );
+        assert_eq!(l.total(), 7);
+    }
 }
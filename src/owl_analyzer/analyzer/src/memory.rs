@@ -5,14 +5,32 @@ use monitor::{
     raw::{RawMemAccessInstr, RawMemAccessRecord},
 };
 
-use crate::hist::ks_test_p_value;
+use crate::{
+    alloc::PoolId,
+    contingency::leaked_bits,
+    cost::{bank_replays, WarpCost},
+    error::{Error, Result},
+    ltest::{Effect, LeakageTest, Sample},
+    observation::Observations,
+};
 
 pub type MemAccess = BTreeMap<TargetAddr, usize>;
 
+/// Exact addresses of a warp access, with the number of times it was recorded
+pub type WarpAccess = (Vec<u64>, usize);
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct MemAccessInstr {
     pub instr: InstrId,
     pub data: Vec<MemAccess>,
+    /// Number of accesses in every merged run
+    pub runs: Observations,
+    /// Bank conflict replays of the shared memory accesses
+    pub conflicts: WarpCost,
+    /// Transactions of the global memory accesses
+    pub transactions: WarpCost,
+    /// Global memory warp accesses of every position, kept until the trace is converted
+    pub global: Vec<Vec<WarpAccess>>,
 }
 
 impl Default for MemAccessInstr {
@@ -20,6 +38,10 @@ impl Default for MemAccessInstr {
         Self {
             instr: InstrId::default(),
             data: Default::default(),
+            runs: Observations::default(),
+            conflicts: WarpCost::default(),
+            transactions: WarpCost::default(),
+            global: Vec::new(),
         }
     }
 }
@@ -40,6 +62,14 @@ impl MemAccessInstr {
         self
     }
 
+    /// Record the current accesses as the observation of a single run
+    pub fn observe_run(&mut self) {
+        let num = self.access_sum().into_iter().sum::<usize>();
+        self.runs = Observations::single(num as f64);
+        self.conflicts.observe_run();
+        self.transactions.observe_run();
+    }
+
     pub fn access_sum(&self) -> Vec<usize> {
         self.data
             .iter()
@@ -47,36 +77,104 @@ impl MemAccessInstr {
             .collect()
     }
 
-    pub fn test(&self, other: &Self, m: usize, n: usize) -> f64 {
-        assert_eq!(self.instr, other.instr);
+    /// Test the accessed addresses of every position, per-run observations are padded to
+    /// the `m` and `n` runs of the two sides
+    pub fn test(
+        &self,
+        other: &Self,
+        m: usize,
+        n: usize,
+        test: &dyn LeakageTest,
+    ) -> Result<InstrOutcome> {
+        if self.instr != other.instr {
+            return Err(Error::Align(format!(
+                "instruction {} is aligned with instruction {}",
+                self.instr, other.instr
+            )));
+        }
         // log::info!()
         log::debug!("{:?}", self.data);
         log::debug!("{:?}", other.data);
+        let (l_runs, r_runs) = (self.runs.padded(m), other.runs.padded(n));
         let mut minimum = 100.0;
+        let mut effect = Effect::default();
+        let mut bits = 0f64;
+        let mut positions = Vec::new();
+        let mut addr = None;
         self.data
             .iter()
             .zip(other.data.iter())
-            .map(|(l, r)| test_mem_impl(l, r, m, n))
-            .for_each(|p| {
+            .map(|(l, r)| {
+                let shifted = most_shifted(l, r);
+                let (lh, rh) = aligned_access(l, r);
+                let (l, r) = (
+                    Sample {
+                        hist: &lh,
+                        runs: &l_runs,
+                    },
+                    Sample {
+                        hist: &rh,
+                        runs: &r_runs,
+                    },
+                );
+                let outcome = test.test(&l, &r);
+                (
+                    outcome.p,
+                    Effect::new(&l, &r, outcome.statistic),
+                    leaked_bits(&lh, &rh, &l_runs, &r_runs),
+                    shifted,
+                )
+            })
+            .for_each(|(p, e, b, a)| {
                 if p < minimum {
                     minimum = p;
+                    effect = e;
+                    addr = a;
                 }
+                bits = bits.max(b);
+                positions.push(p);
             });
 
-        minimum
+        Ok(InstrOutcome {
+            p: minimum,
+            effect,
+            bits,
+            positions,
+            untestable: self.data.len().abs_diff(other.data.len()),
+            addr,
+        })
     }
 }
 
+/// Outcome of the access positions of an instruction
+pub struct InstrOutcome {
+    /// Smallest p value of any position
+    pub p: f64,
+    /// Effect of the position with the smallest p value
+    pub effect: Effect,
+    /// Most bits leaked of any position
+    pub bits: f64,
+    /// p value of every position accessed on both sides
+    pub positions: Vec<f64>,
+    /// Positions accessed on one side only
+    pub untestable: usize,
+    /// Address whose share of the accesses differs most at the position with the smallest
+    /// p value
+    pub addr: Option<TargetAddr>,
+}
+
+/// Accessed address, keyed by its pool, offset and memory type
 #[derive(Debug, Clone, Copy, serde::Serialize)]
 pub struct TargetAddr {
     pub offset: u64,
-    pub pool: Option<u64>,
+    /// Pool the offset is relative to, None if the address is in no recorded pool
+    pub pool: Option<PoolId>,
     // host: bool,
     pub ty: MemType,
 }
 
 impl TargetAddr {
-    pub fn new(offset: u64, pool: Option<u64>, ty: MemType) -> Self {
+    pub fn new(offset: u64, pool: Option<PoolId>, ty: MemType) -> Self {
         Self { offset, pool, ty }
     }
 
@@ -93,6 +191,11 @@ impl PartialOrd for TargetAddr {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         // self.offset.partial_cmp(other)
 
+        match self.pool.partial_cmp(&other.pool) {
+            Some(core::cmp::Ordering::Equal) => {}
+            ord => return ord,
+        }
+
         match self.offset.partial_cmp(&other.offset) {
             Some(core::cmp::Ordering::Equal) => {}
             ord => return ord,
@@ -111,7 +214,7 @@ impl Ord for TargetAddr {
 
 impl PartialEq for TargetAddr {
     fn eq(&self, other: &Self) -> bool {
-        self.offset == other.offset && self.ty == other.ty
+        self.pool == other.pool && self.offset == other.offset && self.ty == other.ty
     }
 }
 
@@ -127,6 +230,20 @@ impl MemAccessRecord {
         Self { instrs: Vec::new() }
     }
 
+    /// Record the current accesses of every instruction as the observation of a single run
+    pub fn observe_run(&mut self) {
+        self.instrs.iter_mut().for_each(|instr| instr.observe_run());
+    }
+
+    /// Instructions after the first `skip` ones and their access positions
+    pub fn count_from(&self, skip: usize) -> (usize, usize) {
+        let rest = self.instrs.iter().skip(skip);
+        (
+            rest.clone().count(),
+            rest.map(|instr| instr.data.len()).sum(),
+        )
+    }
+
     /// Add memory access record of instruction
     pub fn add_instr_mem_access(
         &mut self,
@@ -153,67 +270,49 @@ impl MemAccessRecord {
     }
 }
 
-fn test_mem_impl(l: &MemAccess, r: &MemAccess, m: usize, n: usize) -> f64 {
-    match (l.is_empty(), r.is_empty()) {
-        (true, true) => return ks_test_p_value(0.0, m, n),
-        (false, false) => {}
-        _ => return ks_test_p_value(1.0, m, n),
-    }
-
-    let l_weight = 1.0
-        / l.iter()
-            .filter(|(v, _)| v.is_valid())
-            .map(|(_, c)| *c)
-            .sum::<usize>() as f64;
-    let r_weight = 1.0
-        / r.iter()
-            .filter(|(v, _)| v.is_valid())
-            .map(|(_, c)| *c)
-            .sum::<usize>() as f64;
-
-    let mut l_cdf = 0.0;
-    let mut r_cdf = 0.0;
-    let mut max_diff = 0.0;
-
-    let mut l_iter = l.iter().filter(|(v, _)| v.is_valid());
-    let mut r_iter = r.iter().filter(|(v, _)| v.is_valid());
-
-    let mut l_cur = l_iter.next();
-    let mut r_cur = r_iter.next();
+/// Access counts of both sides over every valid address of either, in address order
+fn aligned_access(l: &MemAccess, r: &MemAccess) -> (Vec<f64>, Vec<f64>) {
+    let mut l_iter = l.iter().filter(|(v, _)| v.is_valid()).peekable();
+    let mut r_iter = r.iter().filter(|(v, _)| v.is_valid()).peekable();
 
+    let mut lh = Vec::new();
+    let mut rh = Vec::new();
     loop {
-        match (l_cur, r_cur) {
+        let (l_num, r_num) = match (l_iter.peek(), r_iter.peek()) {
             (None, None) => break,
-            (None, Some((_, r_num))) => {
-                // log::deb!("r: {}: {r_num}", r_addr.offset);
-                r_cur = r_iter.next();
-                r_cdf += *r_num as f64 * r_weight;
-            }
-            (Some((_, l_num)), None) => {
-                l_cur = l_iter.next();
-                l_cdf += *l_num as f64 * l_weight;
-            }
-            (Some((l_addr, l_num)), Some((r_addr, r_num))) => {
+            (Some(_), None) => (l_iter.next().map(|v| *v.1), None),
+            (None, Some(_)) => (None, r_iter.next().map(|v| *v.1)),
+            (Some((l_addr, _)), Some((r_addr, _))) => {
                 if l_addr < r_addr {
-                    l_cur = l_iter.next();
-                    l_cdf += *l_num as f64 * l_weight;
+                    (l_iter.next().map(|v| *v.1), None)
                 } else if l_addr > r_addr {
-                    r_cur = r_iter.next();
-                    r_cdf += *r_num as f64 * r_weight;
+                    (None, r_iter.next().map(|v| *v.1))
                 } else {
-                    l_cur = l_iter.next();
-                    l_cdf += *l_num as f64 * l_weight;
-                    r_cur = r_iter.next();
-                    r_cdf += *r_num as f64 * r_weight;
+                    (l_iter.next().map(|v| *v.1), r_iter.next().map(|v| *v.1))
                 }
             }
-        }
-        if (l_cdf - r_cdf).abs() > max_diff {
-            max_diff = (l_cdf - r_cdf).abs();
-        }
+        };
+        lh.push(l_num.unwrap_or(0) as f64);
+        rh.push(r_num.unwrap_or(0) as f64);
     }
 
-    ks_test_p_value(max_diff, m, n)
+    (lh, rh)
+}
+
+/// Address whose share of the accesses differs most between both sides
+fn most_shifted(l: &MemAccess, r: &MemAccess) -> Option<TargetAddr> {
+    let (l_sum, r_sum) = (
+        l.values().sum::<usize>().max(1) as f64,
+        r.values().sum::<usize>().max(1) as f64,
+    );
+    let share = |m: &MemAccess, addr, sum| m.get(addr).copied().unwrap_or_default() as f64 / sum;
+
+    l.keys()
+        .chain(r.keys())
+        .map(|addr| (addr, (share(l, addr, l_sum) - share(r, addr, r_sum)).abs()))
+        .filter(|(_, shift)| *shift > 0.0)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(addr, _)| *addr)
 }
 
 impl AddAssign for MemAccessInstr {
@@ -241,6 +340,10 @@ impl AddAssign for MemAccessInstr {
                 self.data.push(std::mem::take(v));
             }
         }
+
+        self.runs.merge(rhs.runs);
+        self.conflicts.merge(rhs.conflicts);
+        self.transactions.merge(rhs.transactions);
     }
 }
 
@@ -275,6 +378,24 @@ impl From<RawMemAccessInstr> for MemAccessInstr {
     fn from(value: RawMemAccessInstr) -> Self {
         let mut new = MemAccessInstr::default().instr(value.addr);
         value.data.into_iter().enumerate().for_each(|(pos, r_m)| {
+            // every shared memory warp access is replayed on its own
+            r_m.access
+                .iter()
+                .filter(|mty| mty.ty == MemType::Shared)
+                .flat_map(|mty| mty.warps.iter())
+                .for_each(|warp| {
+                    let replays = bank_replays(warp.addr.iter().copied());
+                    new.conflicts.add(pos, replays, warp.count);
+                });
+            new.global.push(
+                r_m.access
+                    .iter()
+                    .filter(|mty| matches!(mty.ty, MemType::Global | MemType::Generic))
+                    .flat_map(|mty| mty.warps.iter())
+                    .map(|warp| (warp.addr.clone(), warp.count))
+                    .collect(),
+            );
+
             r_m.access.into_iter().for_each(|mty| {
                 let ty = mty.ty;
                 mty.memory.into_iter().for_each(|m| {
@@ -282,7 +403,55 @@ impl From<RawMemAccessInstr> for MemAccessInstr {
                 });
             })
         });
+        new.observe_run();
 
         new
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::MemAccessInstr;
+    use crate::{error::Error, fixture::Kernel, ltest::TestMethod};
+
+    #[test]
+    pub fn test_misaligned_instr() {
+        let l = MemAccessInstr {
+            instr: 0x10,
+            ..Default::default()
+        };
+        let r = MemAccessInstr {
+            instr: 0x20,
+            ..Default::default()
+        };
+
+        assert!(l.test(&l.clone(), 1, 1, &TestMethod::Ks).is_ok());
+        assert!(matches!(
+            l.test(&r, 1, 1, &TestMethod::Ks),
+            Err(Error::Align(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_bank_conflicts() {
+        let instr = |warps: &[(&[u64], usize)]| {
+            let mut raw = Kernel::new().node(1).warps("SHARED", warps).raw();
+            let node = raw.kernels.pop().unwrap().g.nodes.pop().unwrap();
+            MemAccessInstr::from(node.mem_access.into_iter().next().unwrap())
+        };
+
+        // the same words on both sides, every warp access hits distinct banks on the fixed side
+        // and two words of a bank on the random one
+        let fix = instr(&[(&[0, 4], 2), (&[128, 132], 2)]);
+        let rnd = instr(&[(&[0, 128], 2), (&[4, 132], 2)]);
+        assert_eq!(fix.data, rnd.data);
+        assert_eq!(fix.conflicts.data, vec![BTreeMap::from([(0, 4)])]);
+        assert_eq!(rnd.conflicts.data, vec![BTreeMap::from([(1, 4)])]);
+        assert_eq!(
+            (fix.conflicts.runs.mean(), rnd.conflicts.runs.mean()),
+            (0.0, 4.0)
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use serde::Serialize;
+
+use crate::hist::student_t_p_value;
+
+/// Per-run summary of a site, one value for every run the site is merged from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Observations {
+    values: Vec<f64>,
+}
+
+/// Sample size and moments of the per-run observations
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct RunSummary {
+    pub n: usize,
+    pub mean: f64,
+    pub variance: f64,
+}
+
+impl Observations {
+    /// Observation of a single run
+    pub fn single(value: f64) -> Self {
+        Self {
+            values: vec![value],
+        }
+    }
+
+    /// Append the observations of other runs
+    pub fn merge(&mut self, other: Self) {
+        self.values.extend(other.values);
+    }
+
+    /// Fill the runs a site was not reached in with zero, up to `num` runs
+    pub fn padded(&self, num: usize) -> Self {
+        let mut values = self.values.clone();
+        if values.len() < num {
+            values.resize(num, 0.0);
+        }
+        Self { values }
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Number of runs the site is reached in
+    pub fn reached(&self) -> usize {
+        self.values.iter().filter(|v| **v != 0.0).count()
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.values.is_empty() {
+            return 0.0;
+        }
+        self.values.iter().sum::<f64>() / self.len() as f64
+    }
+
+    /// Unbiased sample variance, zero for less than two runs
+    pub fn variance(&self) -> f64 {
+        if self.len() < 2 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        self.values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (self.len() - 1) as f64
+    }
+
+    pub fn summary(&self) -> RunSummary {
+        RunSummary {
+            n: self.len(),
+            mean: self.mean(),
+            variance: self.variance(),
+        }
+    }
+
+    /// p value of Welch's t-test on the means of the two sides, every run is one sample
+    pub fn welch(&self, other: &Self) -> f64 {
+        self.welch_test(other).1
+    }
+
+    /// t statistic and p value of Welch's t-test, the statistic is infinite if both sides are
+    /// constant at different means
+    pub fn welch_test(&self, other: &Self) -> (f64, f64) {
+        let (n1, n2) = (self.len() as f64, other.len() as f64);
+        if n1 < 2.0 || n2 < 2.0 {
+            return (0.0, 1.0);
+        }
+
+        let diff = self.mean() - other.mean();
+        let (s1, s2) = (self.variance() / n1, other.variance() / n2);
+        let se = s1 + s2;
+
+        // both sides are constant, only the means tell them apart
+        if se == 0.0 {
+            return if diff == 0.0 {
+                (0.0, 1.0)
+            } else {
+                (diff.signum() * f64::INFINITY, 0.0)
+            };
+        }
+
+        // Welch-Satterthwaite degrees of freedom
+        let df = se * se / (s1 * s1 / (n1 - 1.0) + s2 * s2 / (n2 - 1.0));
+        let t = diff / se.sqrt();
+        (t, student_t_p_value(t, df))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Observations;
+
+    fn observations(values: &[f64]) -> Observations {
+        let mut obs = Observations::default();
+        values
+            .iter()
+            .for_each(|v| obs.merge(Observations::single(*v)));
+        obs
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    pub fn test_observations() {
+        let a = observations(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(a.len(), 5);
+        assert_close(a.mean(), 3.0);
+        assert_close(a.variance(), 2.5);
+
+        let padded = observations(&[2.0, 4.0]).padded(4);
+        assert_eq!(padded.values(), &[2.0, 4.0, 0.0, 0.0]);
+        assert_close(padded.mean(), 1.5);
+        assert_eq!(Observations::single(7.0).variance(), 0.0);
+    }
+
+    #[test]
+    pub fn test_welch() {
+        let a = observations(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+        let b = observations(&[2.0, 4.0, 6.0, 8.0, 10.0]);
+        // t = -1.897, df = 5.882
+        assert_close(a.welch(&b), 0.10753119493062518);
+        assert_close(b.welch(&a), 0.10753119493062518);
+        assert_close(a.welch(&a), 1.0);
+        assert_close(a.welch_test(&b).0, -1.8973665961010275);
+
+        // constant runs
+        let c = observations(&[3.0, 3.0, 3.0]);
+        assert_eq!(c.welch(&c), 1.0);
+        assert_eq!(c.welch(&observations(&[4.0, 4.0])), 0.0);
+
+        // a single run is no sample of the run-to-run variation
+        assert_eq!(Observations::single(1.0).welch(&b), 1.0);
+    }
+}
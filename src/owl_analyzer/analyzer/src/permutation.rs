@@ -0,0 +1,265 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dtest::{SiteKey, SiteKind, TestResult},
+    error::Result,
+    evidence::Evidence,
+    rng::Rng,
+    trace::Trace,
+};
+
+/// Lower and upper percentile of the bootstrap interval
+const INTERVAL: (f64, f64) = (0.025, 0.975);
+
+/// Resampling of the recorded runs, the p value of every site is the share of label
+/// permutations testing at least as extreme, the leaked bits get a bootstrap interval
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Permutation {
+    /// Number of permutations of the fixed and random labels
+    pub rounds: usize,
+    /// Number of bootstrap resamples of each side, no interval if zero
+    pub bootstrap: usize,
+    /// Seed of the first resample, random if not set
+    pub seed: Option<u64>,
+    /// Number of resamples tested at the same time
+    pub jobs: usize,
+}
+
+/// Extreme permutations and bootstrap bits of the sites a worker tested
+#[derive(Default)]
+struct Tally {
+    extreme: HashMap<SiteKey, usize>,
+    bits: HashMap<SiteKey, Vec<f64>>,
+}
+
+impl Tally {
+    fn add(&mut self, other: Tally) {
+        for (key, count) in other.extreme {
+            *self.extreme.entry(key).or_default() += count;
+        }
+        for (key, bits) in other.bits {
+            self.bits.entry(key).or_default().extend(bits);
+        }
+    }
+}
+
+/// Bits of every site of `res`, unpaired nodes included
+fn site_bits(res: &TestResult) -> impl Iterator<Item = (SiteKey, f64)> + '_ {
+    res.eq_kernel.iter().flat_map(|k| {
+        let ctx = k.ctx.to_string();
+        let cf =
+            k.cf.iter()
+                .map(move |cf| ((ctx.clone(), k.ty, SiteKind::Cf, cf.id, None), cf.bits));
+        let ctx = k.ctx.to_string();
+        let df = k.df.iter().map(move |df| {
+            (
+                (ctx.clone(), k.ty, SiteKind::Df, df.id, Some(df.instr)),
+                df.bits,
+            )
+        });
+        let ctx = k.ctx.to_string();
+        let cost = k.costs().map(move |(kind, cost)| {
+            (
+                (ctx.clone(), k.ty, kind, cost.id, Some(cost.instr)),
+                cost.bits,
+            )
+        });
+        cf.chain(df).chain(cost)
+    })
+}
+
+impl Permutation {
+    /// Resample the runs of `fix` and `rnd` and replace the p values of `res` by empirical
+    /// ones, attach bootstrap intervals of the leaked bits.
+    ///
+    /// Traces can't be shared between threads, every worker loads the runs by `load` and tests
+    /// each resample by `test`. Resample `i` is drawn from the seed plus `i`, so the result
+    /// does not depend on the number of jobs
+    pub fn apply(
+        &self,
+        res: &mut TestResult,
+        load: impl Fn() -> Result<(Vec<Trace>, Vec<Trace>)> + Sync,
+        test: impl Fn(Evidence, Evidence, usize, usize) -> Result<TestResult> + Sync,
+    ) -> Result<()> {
+        let seed = self.seed.unwrap_or_else(Rng::seed);
+        let total = self.rounds + self.bootstrap;
+        log::info!(
+            "{} permutations, {} bootstrap resamples, seed: {seed}",
+            self.rounds,
+            self.bootstrap
+        );
+
+        let observed: HashMap<SiteKey, f64> =
+            res.sites().map(|site| (site.key(), site.p_value)).collect();
+
+        let next = AtomicUsize::new(0);
+        let workers = self.jobs.clamp(1, total.max(1));
+
+        let tallies: Vec<Result<Tally>> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    s.spawn(|| {
+                        let (fix, rnd) = load()?;
+                        let (n, m) = (fix.len(), rnd.len());
+                        let runs: Vec<_> = fix.into_iter().chain(rnd).collect();
+
+                        let mut tally = Tally::default();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            if i >= total || n == 0 || m == 0 {
+                                break;
+                            }
+
+                            let mut rng = Rng::new(seed.wrapping_add(i as u64));
+                            let (l, r) = if i < self.rounds {
+                                let mut idx: Vec<_> = (0..n + m).collect();
+                                rng.shuffle(&mut idx);
+                                let r = idx.split_off(n);
+                                (idx, r)
+                            } else {
+                                let l = (0..n).map(|_| rng.below(n)).collect();
+                                let r = (0..m).map(|_| n + rng.below(m)).collect();
+                                (l, r)
+                            };
+
+                            let evidence = |idx: &[usize]| -> Result<Evidence> {
+                                let mut evidence = Evidence::default();
+                                for i in idx {
+                                    evidence.merge_trace(runs[*i].clone())?;
+                                }
+                                Ok(evidence)
+                            };
+                            let resampled = test(evidence(&l)?, evidence(&r)?, n, m)?;
+
+                            if i < self.rounds {
+                                for site in resampled.sites() {
+                                    let key = site.key();
+                                    // the same labels test to the same p value up to rounding
+                                    match observed.get(&key) {
+                                        Some(p) if site.p_value <= p * (1.0 + 1e-9) => {
+                                            *tally.extreme.entry(key).or_default() += 1;
+                                        }
+                                        _ => {}
+                                    }
+                                }
+                            } else {
+                                for (key, bits) in site_bits(&resampled) {
+                                    tally.bits.entry(key).or_default().push(bits);
+                                }
+                            }
+                        }
+                        Ok(tally)
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("resampling worker panicked"))
+                .collect()
+        });
+
+        let mut tally = Tally::default();
+        for t in tallies {
+            tally.add(t?);
+        }
+
+        let empirical = |key: &SiteKey, p: &mut f64| {
+            if self.rounds > 0 {
+                let extreme = tally.extreme.get(key).copied().unwrap_or_default();
+                *p = (1 + extreme) as f64 / (1 + self.rounds) as f64;
+            }
+        };
+        let interval = |key: &SiteKey| {
+            if self.bootstrap == 0 {
+                return None;
+            }
+            // a site missing from a resample leaks nothing in it
+            let mut bits = tally.bits.get(key).cloned().unwrap_or_default();
+            bits.resize(self.bootstrap, 0.0);
+            bits.sort_by(f64::total_cmp);
+            let at = |q: f64| bits[((bits.len() - 1) as f64 * q).round() as usize];
+            Some((at(INTERVAL.0), at(INTERVAL.1)))
+        };
+
+        for k in res.eq_kernel.iter_mut() {
+            let ctx = k.ctx.to_string();
+            for cf in k.cf.iter_mut() {
+                let key = (ctx.clone(), k.ty, SiteKind::Cf, cf.id, None);
+                if !cf.unpaired {
+                    empirical(&key, &mut cf.p_value);
+                    cf.p_adjusted = cf.p_value;
+                }
+                cf.bits_ci = interval(&key);
+            }
+            for df in k.df.iter_mut() {
+                let key = (ctx.clone(), k.ty, SiteKind::Df, df.id, Some(df.instr));
+                empirical(&key, &mut df.p_value);
+                df.p_adjusted = df.p_value;
+                df.bits_ci = interval(&key);
+            }
+            let ty = k.ty;
+            for (kind, cost) in k.costs_mut() {
+                let key = (ctx.clone(), ty, kind, cost.id, Some(cost.instr));
+                empirical(&key, &mut cost.p_value);
+                cost.p_adjusted = cost.p_value;
+                cost.bits_ci = interval(&key);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Permutation;
+    use crate::{
+        fixture::{Kernel, TempDir},
+        Analyzer,
+    };
+
+    #[test]
+    pub fn test_permutation() {
+        let root = TempDir::new("permutation");
+        for idx in 0..3 {
+            Kernel::new().node(1).write(root.path(), "fix", idx);
+            Kernel::new().node(2).write(root.path(), "rnd", idx);
+        }
+
+        let analyze = |jobs| {
+            Analyzer {
+                trace_path: root.path().to_owned(),
+                threshold: 0.5,
+                permutation: Some(Permutation {
+                    rounds: 200,
+                    bootstrap: 50,
+                    seed: Some(7),
+                    jobs,
+                }),
+                ..Default::default()
+            }
+            .analyze()
+            .unwrap()
+        };
+
+        let report = analyze(1);
+        let leak = report.cf_leak.values().flatten().next().unwrap();
+        // 2 of the 20 labelings separate the runs, the observed one among them
+        assert!(leak.p > 0.05 && leak.p < 0.2, "{}", leak.p);
+        assert_eq!(leak.p, leak.p_adjusted);
+        // every resample keeps the classes apart
+        assert_eq!(leak.bits_ci, Some((1.0, 1.0)));
+        assert_eq!(report.permutation.unwrap().seed, Some(7));
+
+        // resamples are seeded one by one, the number of jobs doesn't matter
+        let parallel = analyze(3);
+        let other = parallel.cf_leak.values().flatten().next().unwrap();
+        assert_eq!(leak.p, other.p);
+    }
+}
@@ -1,16 +1,28 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
     hash::Hash,
     rc::Rc,
+    str::FromStr,
 };
 
 use monitor::cuda::{BBId, InstrId, KernelTy};
-use serde::{ser::SerializeStruct, Serialize};
+use serde::{ser::SerializeStruct, Deserialize, Serialize};
 
 use crate::{
-    dtest::{DiffKernelResult, EqKernelResult},
+    adaptive::Sampling,
+    alloc::{Buffer, PoolId},
+    calibration::Calibration,
+    correction::Correction,
+    cost::Coalescing,
+    coverage::Coverage,
+    dtest::{CfCell, DiffKernelResult, EqKernelResult, NodeCostResult},
+    error::{self, Error},
+    granularity::Granularity,
+    ltest::{Effect, TestMethod},
     matrix::CfMatrix,
-    memory::MemAccessRecord,
+    memory::{MemAccessRecord, TargetAddr},
+    observation::RunSummary,
+    permutation::Permutation,
     trace::TraceCtx,
 };
 
@@ -20,6 +32,8 @@ pub struct KernelLeakage {
     pub kernel: Rc<String>,
     pub fix_num: usize,
     pub rnd_num: usize,
+    /// Launched a different number of times under identical inputs too
+    pub nondeterministic: bool,
     // pub p: f64,
 }
 
@@ -33,6 +47,7 @@ impl Serialize for KernelLeakage {
         state.serialize_field("kernel", self.kernel.as_str())?;
         state.serialize_field("fix_num", &self.fix_num)?;
         state.serialize_field("rnd_num", &self.rnd_num)?;
+        state.serialize_field("nondeterministic", &self.nondeterministic)?;
 
         state.end()
     }
@@ -52,15 +67,39 @@ impl Hash for KernelLeakage {
     }
 }
 
+/// Bits a kernel call leaks about the input class, the most of any of its leaking sites
+#[derive(Debug, Clone, Serialize)]
+pub struct KernelBits {
+    pub kernel: String,
+    pub bits: f64,
+}
+
 // #[derive(Serialize)]
 pub struct CFleakage {
     pub kernel: Rc<String>,
     // pub ctx: TraceCtx,
     pub bb: BBId,
     pub p: f64,
+    /// p value after multiple-testing correction
+    pub p_adjusted: f64,
     // pub kernel_name: String,
     pub l_flow: CfMatrix,
     pub r_flow: CfMatrix,
+    /// Executions per fixed and random input run
+    pub fix_runs: RunSummary,
+    pub rnd_runs: RunSummary,
+    /// Welch's t-test on the executions per run
+    pub p_welch: f64,
+    /// Statistic, effect sizes and observations of both sides
+    pub effect: Effect,
+    /// Transitions contributing most to the difference
+    pub cells: Vec<CfCell>,
+    /// Bits leaked about the input class
+    pub bits: f64,
+    /// Bootstrap confidence interval of `bits`
+    pub bits_ci: Option<(f64, f64)>,
+    /// Differs under identical inputs too, not a leakage
+    pub nondeterministic: bool,
 }
 
 impl Serialize for CFleakage {
@@ -74,6 +113,17 @@ impl Serialize for CFleakage {
         state.serialize_field("l_flow", &self.l_flow)?;
         state.serialize_field("r_flow", &self.r_flow)?;
         state.serialize_field("p", &self.p)?;
+        state.serialize_field("p_adjusted", &self.p_adjusted)?;
+        state.serialize_field("fix_runs", &self.fix_runs)?;
+        state.serialize_field("rnd_runs", &self.rnd_runs)?;
+        state.serialize_field("p_welch", &self.p_welch)?;
+        state.serialize_field("effect", &self.effect)?;
+        state.serialize_field("cells", &self.cells)?;
+        state.serialize_field("bits", &self.bits)?;
+        if let Some(ci) = &self.bits_ci {
+            state.serialize_field("bits_ci", ci)?;
+        }
+        state.serialize_field("nondeterministic", &self.nondeterministic)?;
 
         state.end()
     }
@@ -100,8 +150,27 @@ pub struct DFLeakage {
     pub instr: InstrId,
     pub bb: BBId,
     pub p: f64,
+    /// p value after multiple-testing correction
+    pub p_adjusted: f64,
     pub ld: MemAccessRecord,
     pub rd: MemAccessRecord,
+    /// Accesses per fixed and random input run
+    pub fix_runs: RunSummary,
+    pub rnd_runs: RunSummary,
+    /// Welch's t-test on the accesses per run
+    pub p_welch: f64,
+    /// Statistic, effect sizes and observations of both sides
+    pub effect: Effect,
+    /// Bits leaked about the input class
+    pub bits: f64,
+    /// Bootstrap confidence interval of `bits`
+    pub bits_ci: Option<(f64, f64)>,
+    /// Differs under identical inputs too, not a leakage
+    pub nondeterministic: bool,
+    /// Address whose share of the accesses differs most
+    pub addr: Option<TargetAddr>,
+    /// Buffer and offset of `addr`, e.g. `offset 0x40 in buffer allocated at aes.cu:120 (4 KiB)`
+    pub location: Option<String>,
 }
 
 impl PartialEq for DFLeakage {
@@ -131,6 +200,22 @@ impl Serialize for DFLeakage {
         state.serialize_field("instr", &self.instr)?;
         state.serialize_field("bb", &self.bb)?;
         state.serialize_field("p", &self.p)?;
+        state.serialize_field("p_adjusted", &self.p_adjusted)?;
+        state.serialize_field("fix_runs", &self.fix_runs)?;
+        state.serialize_field("rnd_runs", &self.rnd_runs)?;
+        state.serialize_field("p_welch", &self.p_welch)?;
+        state.serialize_field("effect", &self.effect)?;
+        state.serialize_field("bits", &self.bits)?;
+        if let Some(ci) = &self.bits_ci {
+            state.serialize_field("bits_ci", ci)?;
+        }
+        state.serialize_field("nondeterministic", &self.nondeterministic)?;
+        if let Some(addr) = &self.addr {
+            state.serialize_field("addr", addr)?;
+        }
+        if let Some(location) = &self.location {
+            state.serialize_field("location", location)?;
+        }
         // state.serialize_field("ld", &self.ld)?;
         // state.serialize_field("rd", &self.rd)?;
 
@@ -138,6 +223,89 @@ impl Serialize for DFLeakage {
     }
 }
 
+/// Leaking cost of the warp accesses of a memory access instruction, its bank conflict
+/// replays or memory transactions
+pub struct CostLeakage {
+    pub kernel: Rc<String>,
+    pub instr: InstrId,
+    pub bb: BBId,
+    pub p: f64,
+    /// p value after multiple-testing correction
+    pub p_adjusted: f64,
+    /// Summed cost per fixed and random input run
+    pub fix_runs: RunSummary,
+    pub rnd_runs: RunSummary,
+    /// Welch's t-test on the cost per run
+    pub p_welch: f64,
+    /// Statistic, effect sizes and observations of both sides
+    pub effect: Effect,
+    /// Bits leaked about the input class
+    pub bits: f64,
+    /// Bootstrap confidence interval of `bits`
+    pub bits_ci: Option<(f64, f64)>,
+    /// Differs under identical inputs too, not a leakage
+    pub nondeterministic: bool,
+}
+
+impl CostLeakage {
+    fn new(kernel: Rc<String>, res: NodeCostResult) -> Self {
+        Self {
+            kernel,
+            instr: res.instr,
+            bb: res.id,
+            p: res.p_value,
+            p_adjusted: res.p_adjusted,
+            fix_runs: res.l_runs.summary(),
+            rnd_runs: res.r_runs.summary(),
+            p_welch: res.l_runs.welch(&res.r_runs),
+            effect: res.effect,
+            bits: res.bits,
+            bits_ci: res.bits_ci,
+            nondeterministic: res.nondeterministic,
+        }
+    }
+}
+
+impl PartialEq for CostLeakage {
+    fn eq(&self, other: &Self) -> bool {
+        self.bb == other.bb && self.instr == other.instr
+    }
+}
+
+impl Eq for CostLeakage {}
+
+impl Hash for CostLeakage {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.bb.hash(state);
+        self.instr.hash(state);
+    }
+}
+
+impl Serialize for CostLeakage {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("CostLeakage", 4)?;
+        state.serialize_field("kernel", &self.kernel.as_str())?;
+        state.serialize_field("instr", &self.instr)?;
+        state.serialize_field("bb", &self.bb)?;
+        state.serialize_field("p", &self.p)?;
+        state.serialize_field("p_adjusted", &self.p_adjusted)?;
+        state.serialize_field("fix_runs", &self.fix_runs)?;
+        state.serialize_field("rnd_runs", &self.rnd_runs)?;
+        state.serialize_field("p_welch", &self.p_welch)?;
+        state.serialize_field("effect", &self.effect)?;
+        state.serialize_field("bits", &self.bits)?;
+        if let Some(ci) = &self.bits_ci {
+            state.serialize_field("bits_ci", ci)?;
+        }
+        state.serialize_field("nondeterministic", &self.nondeterministic)?;
+
+        state.end()
+    }
+}
+
 // #[derive(Serialize)]
 // pub struct MemoryPosLeakage {
 //     pub kernel: KernelTy,
@@ -183,9 +351,10 @@ impl Hash for MemoryAddrLeakage {
 }
 
 pub struct ReportBuilder {
-    // threshold: f64,
+    threshold: f64,
     report: Report,
     kernels: HashMap<KernelTy, Rc<String>>,
+    buffers: BTreeMap<PoolId, Buffer>,
 }
 
 impl ReportBuilder {
@@ -193,20 +362,47 @@ impl ReportBuilder {
         self.report
     }
 
-    pub fn add_diff_kernel(&mut self, res: DiffKernelResult) {
+    /// Name the memory pools leaking addresses are in
+    pub fn buffers(mut self, buffers: BTreeMap<PoolId, Buffer>) -> Self {
+        self.buffers = buffers;
+        self
+    }
+
+    /// Offset of `addr` in its buffer, None if it is in no recorded pool
+    fn location(&self, addr: &TargetAddr) -> Option<String> {
+        let pool = addr.pool?;
+        let buffer = match self.buffers.get(&pool) {
+            Some(buffer) => buffer.to_string(),
+            None => format!("buffer {}", pool.order),
+        };
+        Some(format!("offset 0x{:x} in {buffer}", addr.offset))
+    }
+
+    /// Name of kernel type `ty`, every tested kernel must be named by its trace
+    fn kernel(&self, ty: KernelTy) -> error::Result<Rc<String>> {
+        self.kernels
+            .get(&ty)
+            .cloned()
+            .ok_or_else(|| Error::Schema(format!("kernel type {ty} has no name")))
+    }
+
+    pub fn add_diff_kernel(&mut self, res: DiffKernelResult) -> error::Result<()> {
         let leakage = KernelLeakage {
             ctx: res.ctx,
-            kernel: self.kernels.get(&res.ty).unwrap().clone(),
+            kernel: self.kernel(res.ty)?,
             fix_num: res.l_num,
             rnd_num: res.r_num,
+            nondeterministic: res.nondeterministic,
         };
 
         self.report.kernel_leak.insert(leakage);
+        Ok(())
     }
 
     /// Save equal kernel test result
-    pub fn add_eq_kernel(&mut self, res: EqKernelResult) {
+    pub fn add_eq_kernel(&mut self, res: EqKernelResult) -> error::Result<()> {
         let ctx = res.ctx;
+        let kernel = self.kernel(res.ty)?;
 
         if !self.report.cf_leak.contains_key(&ctx) {
             self.report.cf_leak.insert(ctx.clone(), HashSet::default());
@@ -218,13 +414,22 @@ impl ReportBuilder {
         set.extend(
             res.cf
                 .into_iter()
-                // .filter(|cf| cf.p_value < self.threshold)
+                .filter(|cf| cf.unpaired || cf.p_adjusted < self.threshold)
                 .map(|cf| CFleakage {
                     bb: cf.id,
                     p: cf.p_value,
+                    p_adjusted: cf.p_adjusted,
+                    fix_runs: cf.l_runs.summary(),
+                    rnd_runs: cf.r_runs.summary(),
+                    p_welch: cf.l_runs.welch(&cf.r_runs),
+                    effect: cf.effect,
+                    cells: cf.cells,
+                    bits: cf.bits,
+                    bits_ci: cf.bits_ci,
+                    nondeterministic: cf.nondeterministic,
                     l_flow: cf.l_flow,
                     r_flow: cf.r_flow,
-                    kernel: self.kernels.get(&res.ty).unwrap().clone(),
+                    kernel: kernel.clone(),
                 }),
         );
 
@@ -232,22 +437,112 @@ impl ReportBuilder {
             self.report.df_leak.insert(ctx.clone(), HashSet::default());
         }
 
+        // add df leakage, attributed to the buffer they touched
+        let leaks: Vec<_> = res
+            .df
+            .into_iter()
+            .filter(|df| df.p_adjusted < self.threshold)
+            .map(|df| {
+                let location = df.addr.as_ref().and_then(|addr| self.location(addr));
+                (df, location)
+            })
+            .collect();
         let set = self.report.df_leak.get_mut(&ctx).unwrap();
+        set.extend(leaks.into_iter().map(|(df, location)| DFLeakage {
+            kernel: kernel.clone(),
+            instr: df.instr,
+            bb: df.id,
+            p: df.p_value,
+            p_adjusted: df.p_adjusted,
+            fix_runs: df.l_runs.summary(),
+            rnd_runs: df.r_runs.summary(),
+            p_welch: df.l_runs.welch(&df.r_runs),
+            effect: df.effect,
+            bits: df.bits,
+            bits_ci: df.bits_ci,
+            nondeterministic: df.nondeterministic,
+            ld: df.ld,
+            rd: df.rd,
+            addr: df.addr,
+            location,
+        }));
+
+        // add bank conflict and coalescing leakage
+        let threshold = self.threshold;
+        for (leak, costs) in [
+            (&mut self.report.bank_leak, res.bank),
+            (&mut self.report.coalescing_leak, res.coalescing),
+        ] {
+            leak.entry(ctx.clone()).or_default().extend(
+                costs
+                    .into_iter()
+                    .filter(|cost| cost.p_adjusted < threshold)
+                    .map(|cost| CostLeakage::new(kernel.clone(), cost)),
+            );
+        }
 
-        // add df leakage
-        set.extend(
-            res.df
-                .into_iter()
-                // .filter(|df| df.p_value < self.threshold)
-                .map(|df| DFLeakage {
-                    kernel: self.kernels.get(&res.ty).unwrap().clone(),
-                    instr: df.instr,
-                    bb: df.id,
-                    p: df.p_value,
-                    ld: df.ld,
-                    rd: df.rd,
-                }),
-        );
+        // the whole call leaks at least as much as any of its sites, nondeterministic ones aside
+        let bits = self.report.cf_leak[&ctx]
+            .iter()
+            .filter(|leak| !leak.nondeterministic)
+            .map(|leak| leak.bits)
+            .chain(
+                self.report.df_leak[&ctx]
+                    .iter()
+                    .filter(|leak| !leak.nondeterministic)
+                    .map(|leak| leak.bits),
+            )
+            .chain(
+                self.report.bank_leak[&ctx]
+                    .iter()
+                    .chain(self.report.coalescing_leak[&ctx].iter())
+                    .filter(|leak| !leak.nondeterministic)
+                    .map(|leak| leak.bits),
+            )
+            .reduce(f64::max);
+        if let Some(bits) = bits {
+            self.report.kernel_bits.insert(
+                ctx,
+                KernelBits {
+                    kernel: kernel.to_string(),
+                    bits,
+                },
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// File format a report is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    /// Full report, `report.json`
+    #[default]
+    Json,
+    /// One leakage per row, `report.csv`
+    Csv,
+}
+
+impl ReportFormat {
+    pub fn ext(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format!("unknown report format `{s}`, expect json or csv")),
+        }
     }
 }
 
@@ -256,15 +551,40 @@ pub struct Report {
     pub kernel_leak: HashSet<KernelLeakage>,
     pub cf_leak: HashMap<TraceCtx, HashSet<CFleakage>>,
     pub df_leak: HashMap<TraceCtx, HashSet<DFLeakage>>,
+    /// Shared memory instructions whose bank conflict replays leak
+    pub bank_leak: HashMap<TraceCtx, HashSet<CostLeakage>>,
+    /// Global memory instructions whose transactions leak
+    pub coalescing_leak: HashMap<TraceCtx, HashSet<CostLeakage>>,
+    /// Lower bound of the bits leaked by every kernel call with a leaking site
+    pub kernel_bits: HashMap<TraceCtx, KernelBits>,
     // pub name_map: HashMap<TraceCtx, String>,
+    /// Multiple-testing correction of the p values compared with the threshold
+    pub correction: Correction,
+    /// Test every site is compared by
+    pub method: TestMethod,
+    /// How sites differing between fixed input runs are reported
+    pub calibration: Calibration,
+    /// Tested, passed, failed and untestable kernels, calls, nodes and instructions
+    pub coverage: Coverage,
+    /// Unit accessed addresses are compared in
+    pub granularity: Granularity,
+    /// Sector size global memory transactions are counted in
+    pub coalescing: Coalescing,
+    /// Number of runs needed and undecided sites of an adaptive campaign
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sampling: Option<Sampling>,
+    /// Resampling the p values and bit intervals are taken from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permutation: Option<Permutation>,
 }
 
 impl Report {
-    pub fn builder(kernels: HashMap<KernelTy, Rc<String>>) -> ReportBuilder {
+    pub fn builder(kernels: HashMap<KernelTy, Rc<String>>, threshold: f64) -> ReportBuilder {
         ReportBuilder {
-            // threshold: 100.0,
+            threshold,
             report: Report::new(),
             kernels,
+            buffers: BTreeMap::new(),
         }
     }
 
@@ -274,7 +594,240 @@ impl Report {
             kernel_leak: Default::default(),
             cf_leak: Default::default(),
             df_leak: Default::default(),
+            bank_leak: Default::default(),
+            coalescing_leak: Default::default(),
+            kernel_bits: Default::default(),
             // name_map: Default::default(),
+            correction: Correction::None,
+            method: TestMethod::default(),
+            calibration: Calibration::None,
+            coverage: Coverage::default(),
+            granularity: Granularity::default(),
+            coalescing: Coalescing::default(),
+            sampling: None,
+            permutation: None,
+        }
+    }
+
+    /// Render the report in `format`
+    pub fn render(&self, format: ReportFormat) -> serde_json::Result<String> {
+        match format {
+            ReportFormat::Json => serde_json::to_string_pretty(self),
+            ReportFormat::Csv => Ok(self.to_csv()),
         }
     }
+
+    /// One row per leakage, rows are sorted to keep the output stable
+    pub fn to_csv(&self) -> String {
+        let mut rows: Vec<[String; 11]> = Vec::new();
+
+        rows.extend(self.kernel_leak.iter().map(|leak| {
+            [
+                "kernel".to_owned(),
+                leak.kernel.to_string(),
+                leak.ctx.to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                leak.fix_num.to_string(),
+                leak.rnd_num.to_string(),
+                String::new(),
+                leak.nondeterministic.to_string(),
+            ]
+        }));
+
+        rows.extend(self.cf_leak.iter().flat_map(|(ctx, set)| {
+            set.iter().map(move |leak| {
+                [
+                    "cf".to_owned(),
+                    leak.kernel.to_string(),
+                    ctx.to_string(),
+                    leak.bb.to_string(),
+                    String::new(),
+                    leak.p.to_string(),
+                    leak.p_adjusted.to_string(),
+                    String::new(),
+                    String::new(),
+                    leak.bits.to_string(),
+                    leak.nondeterministic.to_string(),
+                ]
+            })
+        }));
+
+        rows.extend(self.df_leak.iter().flat_map(|(ctx, set)| {
+            set.iter().map(move |leak| {
+                [
+                    "df".to_owned(),
+                    leak.kernel.to_string(),
+                    ctx.to_string(),
+                    leak.bb.to_string(),
+                    leak.instr.to_string(),
+                    leak.p.to_string(),
+                    leak.p_adjusted.to_string(),
+                    String::new(),
+                    String::new(),
+                    leak.bits.to_string(),
+                    leak.nondeterministic.to_string(),
+                ]
+            })
+        }));
+
+        let costs = self
+            .bank_leak
+            .iter()
+            .map(|leak| ("bank", leak))
+            .chain(self.coalescing_leak.iter().map(|leak| ("coalescing", leak)));
+        rows.extend(costs.flat_map(|(kind, (ctx, set))| {
+            set.iter().map(move |leak| {
+                [
+                    kind.to_owned(),
+                    leak.kernel.to_string(),
+                    ctx.to_string(),
+                    leak.bb.to_string(),
+                    leak.instr.to_string(),
+                    leak.p.to_string(),
+                    leak.p_adjusted.to_string(),
+                    String::new(),
+                    String::new(),
+                    leak.bits.to_string(),
+                    leak.nondeterministic.to_string(),
+                ]
+            })
+        }));
+
+        rows.sort();
+
+        let mut csv = String::from(
+            "kind,kernel,ctx,bb,instr,p,p_adjusted,fix_num,rnd_num,bits,nondeterministic\n",
+        );
+        for row in rows {
+            let fields: Vec<_> = row.iter().map(|f| csv_field(f)).collect();
+            csv.push_str(&fields.join(","));
+            csv.push('\n');
+        }
+
+        csv
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        rc::Rc,
+    };
+
+    use monitor::cuda::MemType;
+
+    use super::Report;
+    use crate::{
+        alloc::{Buffer, PoolId},
+        dtest::{EqKernelResult, NodeCfResult, NodeCostResult, NodeDfResult},
+        memory::TargetAddr,
+    };
+
+    /// Report of a call of kernel `k` at level 0.05
+    fn report(res: EqKernelResult) -> Report {
+        report_buffers(res, BTreeMap::new())
+    }
+
+    /// Report of a call of kernel `k` at level 0.05 naming the pools by `buffers`
+    fn report_buffers(res: EqKernelResult, buffers: BTreeMap<PoolId, Buffer>) -> Report {
+        let kernels = HashMap::from([(1, Rc::new("k".to_owned()))]);
+        let mut builder = Report::builder(kernels, 0.05).buffers(buffers);
+        builder.add_eq_kernel(res).unwrap();
+        builder.build()
+    }
+
+    #[test]
+    pub fn test_kernel_bits() {
+        let mut res = EqKernelResult::new().ty(1);
+        res.push_cf(NodeCfResult::new(0).p_value(0.01).bits(0.5));
+        res.push_cf(NodeCfResult::new(1).p_value(0.01).bits(0.8));
+        // not leaking
+        res.push_cf(NodeCfResult::new(2).p_value(0.5).bits(0.9));
+        // differs between fixed input runs
+        let mut baseline = NodeCfResult::new(3).p_value(0.01).bits(1.0);
+        baseline.nondeterministic = true;
+        res.push_cf(baseline);
+
+        let report = report(res);
+        let bits = report.kernel_bits.values().next().unwrap();
+        assert_eq!((bits.kernel.as_str(), bits.bits), ("k", 0.8));
+    }
+
+    #[test]
+    pub fn test_location() {
+        let pool = |order| PoolId {
+            order,
+            size: 4096,
+            site: 0,
+        };
+        let buffer = Buffer {
+            order: 0,
+            size: 4096,
+            func: Some("cudaMalloc".to_owned()),
+            site: Some("aes.cu:120".to_owned()),
+        };
+        let mut res = EqKernelResult::new().ty(1);
+        for (instr, addr) in [
+            (16, TargetAddr::new(0x80, Some(pool(0)), MemType::Global)),
+            // no allocation record of the pool
+            (32, TargetAddr::new(0x40, Some(pool(1)), MemType::Global)),
+            // in no recorded pool
+            (48, TargetAddr::new(0x7000, None, MemType::Global)),
+        ] {
+            res.push_df(NodeDfResult::new(0, instr).p_value(0.01).addr(Some(addr)));
+        }
+
+        let report = report_buffers(res, BTreeMap::from([(pool(0), buffer)]));
+        let mut locations: Vec<_> = report
+            .df_leak
+            .values()
+            .flatten()
+            .map(|leak| (leak.instr, leak.location.clone()))
+            .collect();
+        locations.sort();
+        assert_eq!(
+            locations,
+            vec![
+                (
+                    16,
+                    Some("offset 0x80 in buffer allocated at aes.cu:120 (4 KiB)".to_owned())
+                ),
+                (32, Some("offset 0x40 in buffer 1".to_owned())),
+                (48, None),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_cost_leaks() {
+        let mut res = EqKernelResult::new().ty(1);
+        res.push_bank(NodeCostResult::new(0, 16).p_value(0.01).bits(0.5));
+        res.push_bank(NodeCostResult::new(1, 32).p_value(0.5));
+        res.push_coalescing(NodeCostResult::new(1, 48).p_value(0.01).bits(0.7));
+
+        let report = report(res);
+        let leaks: Vec<_> = report
+            .bank_leak
+            .values()
+            .flatten()
+            .map(|leak| (leak.bb, leak.instr))
+            .collect();
+        assert_eq!(leaks, vec![(0, 16)]);
+        assert_eq!(report.coalescing_leak.values().flatten().count(), 1);
+        assert_eq!(report.kernel_bits.values().next().unwrap().bits, 0.7);
+        let csv = report.to_csv();
+        assert!(csv.contains("\nbank,k,") && csv.contains("\ncoalescing,k,"));
+    }
 }
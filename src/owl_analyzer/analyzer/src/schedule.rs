@@ -0,0 +1,142 @@
+use std::{fs::File, io::BufReader, ops::Range, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::rng::Rng;
+
+pub const SCHEDULE_FILE: &str = "schedule.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleMode {
+    /// All fixed input executions first, then all random input ones
+    #[default]
+    Sequential,
+    /// Fixed and random input executions shuffled together
+    Interleaved,
+}
+
+impl FromStr for ScheduleMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sequential" => Ok(Self::Sequential),
+            "interleaved" => Ok(Self::Interleaved),
+            _ => Err(format!(
+                "unknown schedule `{s}`, expect sequential or interleaved"
+            )),
+        }
+    }
+}
+
+/// Execution order of a campaign, `order[i]` is the `(stage, idx)` of the i-th execution
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub mode: ScheduleMode,
+    pub seed: Option<u64>,
+    pub order: Vec<(String, usize)>,
+}
+
+impl Schedule {
+    /// Schedule fixed input runs `fix` and random input runs `rnd`
+    pub fn new(
+        mode: ScheduleMode,
+        fix: Range<usize>,
+        rnd: Range<usize>,
+        seed: Option<u64>,
+    ) -> Self {
+        let mut order: Vec<_> = fix
+            .clone()
+            .map(|idx| ("fix".to_owned(), idx))
+            .chain(rnd.map(|idx| ("rnd".to_owned(), idx)))
+            .collect();
+
+        let seed = match mode {
+            ScheduleMode::Sequential => None,
+            ScheduleMode::Interleaved => {
+                let seed = seed.unwrap_or_else(Rng::seed);
+                // later batches of the same campaign get their own permutation
+                Rng::new(seed.wrapping_add(fix.start as u64)).shuffle(&mut order);
+                Some(seed)
+            }
+        };
+
+        Self { mode, seed, order }
+    }
+
+    /// Append the order of a later batch, runs already scheduled are kept at their place
+    pub fn extend(&mut self, other: Self) {
+        for run in other.order {
+            if !self.order.contains(&run) {
+                self.order.push(run);
+            }
+        }
+    }
+
+    /// Every run of `other` is scheduled by `self`
+    pub fn contains(&self, other: &Self) -> bool {
+        other.order.iter().all(|run| self.order.contains(run))
+    }
+
+    pub fn save(&self, root_path: &str) -> std::io::Result<()> {
+        std::fs::create_dir_all(root_path)?;
+        let file = File::create(format!("{root_path}/{SCHEDULE_FILE}"))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn load(root_path: &str) -> std::io::Result<Self> {
+        let reader = BufReader::new(File::open(format!("{root_path}/{SCHEDULE_FILE}"))?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Schedule, ScheduleMode};
+
+    #[test]
+    pub fn test_schedule() {
+        let seq = Schedule::new(ScheduleMode::Sequential, 0..3, 0..2, Some(7));
+        assert_eq!(seq.seed, None);
+        assert_eq!(
+            seq.order,
+            vec![
+                ("fix".to_owned(), 0),
+                ("fix".to_owned(), 1),
+                ("fix".to_owned(), 2),
+                ("rnd".to_owned(), 0),
+                ("rnd".to_owned(), 1),
+            ]
+        );
+
+        let a = Schedule::new(ScheduleMode::Interleaved, 0..16, 0..16, Some(7));
+        let b = Schedule::new(ScheduleMode::Interleaved, 0..16, 0..16, Some(7));
+        let c = Schedule::new(ScheduleMode::Interleaved, 0..16, 0..16, Some(8));
+        assert_eq!(a, b);
+        assert_ne!(a.order, c.order);
+
+        // every execution is scheduled exactly once
+        let mut sorted = a.order.clone();
+        sorted.sort();
+        let mut expect = Schedule::new(ScheduleMode::Sequential, 0..16, 0..16, None).order;
+        expect.sort();
+        assert_eq!(sorted, expect);
+
+        // classes are mixed instead of blocked
+        assert!(a.order[..16].iter().any(|(stage, _)| stage == "rnd"));
+
+        // a later batch only schedules new runs
+        let next = Schedule::new(ScheduleMode::Interleaved, 16..20, 16..20, Some(7));
+        assert_eq!(next.order.len(), 8);
+        assert!(next.order.iter().all(|(_, idx)| *idx >= 16));
+
+        let mut all = a.clone();
+        all.extend(next.clone());
+        all.extend(next.clone());
+        assert_eq!(all.order.len(), 40);
+        assert!(all.contains(&next) && all.contains(&a));
+        assert!(!a.contains(&next));
+    }
+}
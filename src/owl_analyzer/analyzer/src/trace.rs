@@ -1,21 +1,32 @@
 use crate::{
-    alloc::MemPool,
+    alloc::{Allocation, Buffer, MemPool, PoolId},
+    cost::{Coalescing, WarpCost},
     dcfg::{Node, TDcfg},
     dtest::EqKernelResult,
+    error::{self, Error},
+    granularity::Granularity,
     kernel::KernelCall,
+    ltest::LeakageTest,
     matrix::CfMatrix,
     memory::MemAccessRecord,
-    merge::Merge,
 };
 use monitor::{
     cuda::{KernelId, KernelTy},
     raw::{RawCsFrame, RawDCFG, RawKernelTrace, RawTrace},
 };
-use std::{collections::BTreeMap, fmt::Debug, rc::Rc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+    rc::Rc,
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Trace {
     pub kernels: Vec<KernelCall>,
+    /// Host side device allocations, in allocation order
+    pub allocs: Vec<Allocation>,
+    /// Every memory pool of a kernel launch, named by its allocation
+    pub buffers: BTreeMap<PoolId, Buffer>,
 }
 
 impl Trace {
@@ -59,11 +70,8 @@ impl PartialEq for TraceCtx {
     }
 }
 
-impl serde::Serialize for TraceCtx {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
+impl std::fmt::Display for TraceCtx {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str: String = self
             .cs
             .iter()
@@ -71,7 +79,16 @@ impl serde::Serialize for TraceCtx {
             .collect::<Vec<_>>()
             .join("/");
 
-        serializer.serialize_str(&str)
+        f.write_str(&str)
+    }
+}
+
+impl serde::Serialize for TraceCtx {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
     }
 }
 
@@ -87,6 +104,11 @@ impl TraceCtx {
         self.cs.is_empty()
     }
 
+    /// Source location of the innermost frame with a file
+    pub fn location(&self) -> Option<String> {
+        self.cs.iter().find_map(|f| f.location())
+    }
+
     pub fn from_raw(value: impl Iterator<Item = impl Into<CallFrame>>) -> Self {
         Self {
             // name: String::new(),
@@ -117,6 +139,15 @@ impl PartialEq for CallFrame {
 
 impl Eq for CallFrame {}
 
+impl CallFrame {
+    /// `file:offset` of the frame if its file is known
+    pub fn location(&self) -> Option<String> {
+        self.file
+            .as_ref()
+            .map(|file| format!("{file}:{}", self.offset))
+    }
+}
+
 impl From<RawCsFrame> for CallFrame {
     fn from(value: RawCsFrame) -> Self {
         Self {
@@ -132,23 +163,39 @@ impl From<RawCsFrame> for CallFrame {
     }
 }
 
-impl From<RawTrace> for Trace {
-    fn from(value: RawTrace) -> Self {
+impl TryFrom<RawTrace> for Trace {
+    type Error = Error;
+
+    fn try_from(value: RawTrace) -> error::Result<Self> {
+        Self::from_raw(value, &Granularity::default(), &Coalescing::default())
+    }
+}
+
+impl Trace {
+    /// Convert a recorded trace, every accessed address is made relative to its memory pool
+    /// and bucketed by `granularity`, global memory transactions are counted by `coalescing`
+    pub fn from_raw(
+        value: RawTrace,
+        granularity: &Granularity,
+        coalescing: &Coalescing,
+    ) -> error::Result<Self> {
+        check_raw_trace(&value)?;
+
+        let allocs = Allocation::from_raw(value.alloc, value.context);
+        let mut buffers = BTreeMap::new();
+
         let calls = value
             .kernels
             .into_iter()
             // build mem_pools for kernel trace
             .map(|v| {
+                // pools are identified by their order, their base address changes between runs
                 let pools: Vec<_> =
                     v.mp.iter()
-                        .map(|d| {
-                            let p = MemPool {
-                                start: d.addr,
-                                size: d.size,
-                            };
-                            p
-                        })
+                        .enumerate()
+                        .map(|(order, d)| MemPool::new(order, d).alloc(&allocs))
                         .collect();
+                buffers.extend(pools.iter().map(|p| (p.id, p.buffer())));
                 (v, pools)
             })
             // build kernel trace
@@ -164,40 +211,82 @@ impl From<RawTrace> for Trace {
                     let mut mem_access = MemAccessRecord::new();
 
                     // for every memory access in the old record
-                    for e_instr in node.mem_access.instrs.iter() {
+                    for e_instr in node.mem_access.instrs.iter_mut() {
+                        // every global memory warp access is costed on its own
+                        let mut transactions = WarpCost::default();
+                        for (pos, warps) in e_instr.global.iter().enumerate() {
+                            for (addrs, count) in warps {
+                                let cost = coalescing.transactions(addrs.iter().copied());
+                                transactions.add(pos, cost, *count);
+                            }
+                        }
+
                         for (pos, mem) in e_instr.data.iter().enumerate() {
                             for (addr, num) in mem.iter() {
                                 // convert to offset
-                                if let Some(addr) = mem_pools.iter().find_map(|p| p.convert(*addr))
+                                let addr = if let Some(addr) =
+                                    mem_pools.iter().find_map(|p| p.convert(*addr))
                                 {
                                     // in-pool memory access
-                                    mem_access.add_instr_mem_access(e_instr.instr, pos, addr, *num);
+                                    addr
                                 } else {
                                     // out-pool memory access
-                                    mem_access.add_instr_mem_access(
-                                        e_instr.instr,
-                                        pos,
-                                        *addr,
-                                        *num,
-                                    );
+                                    *addr
                                 };
+                                mem_access.add_instr_mem_access(
+                                    e_instr.instr,
+                                    pos,
+                                    granularity.apply(addr),
+                                    *num,
+                                );
                             }
                         }
+
+                        // bank conflicts and transactions are taken from the exact addresses
+                        if let Some(instr) = mem_access
+                            .instrs
+                            .iter_mut()
+                            .find(|instr| instr.instr == e_instr.instr)
+                        {
+                            instr.conflicts = std::mem::take(&mut e_instr.conflicts);
+                            instr.transactions = transactions;
+                        }
                     }
 
-                    // update node's mem_access
+                    // update node's mem_access, the observation of this run is kept
                     node.mem_access = mem_access;
+                    node.mem_access.observe_run();
                 });
 
                 KernelCall::new(TraceCtx::from_raw(ctx.into_iter()), kernel)
             })
             .collect();
 
-        Self { kernels: calls }
+        Ok(Self {
+            kernels: calls,
+            allocs,
+            buffers,
+        })
         // todo!()
     }
 }
 
+/// Every node of a kernel graph is dumped once
+fn check_raw_trace(trace: &RawTrace) -> error::Result<()> {
+    for kernel in trace.kernels.iter() {
+        let mut ids = BTreeSet::new();
+        if let Some(node) = kernel.g.nodes.iter().find(|n| !ids.insert(n.id)) {
+            return Err(Error::Schema(format!(
+                "node {} of kernel {} `{}` is dumped twice",
+                node.id, kernel.id, kernel.name
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Clone)]
 pub struct KernelTrace {
     pub id: KernelId,
     pub ty: KernelTy,
@@ -206,12 +295,22 @@ pub struct KernelTrace {
 }
 
 impl KernelTrace {
-    pub fn test(self, other: Self, n: usize, m: usize, threshold: f64) -> EqKernelResult {
-        assert_eq!(self.addr, other.addr);
-        assert_eq!(self.ty, other.ty);
+    pub fn test(
+        self,
+        other: Self,
+        n: usize,
+        m: usize,
+        test: &dyn LeakageTest,
+    ) -> error::Result<EqKernelResult> {
+        if self.addr != other.addr || self.ty != other.ty {
+            return Err(Error::Align(format!(
+                "kernel {} at 0x{:x} is aligned with kernel {} at 0x{:x}",
+                self.ty, self.addr, other.ty, other.addr
+            )));
+        }
 
         log::debug!("Testing DCFG");
-        self.g.test(other.g, n, m, threshold).ty(self.ty)
+        Ok(self.g.test(other.g, n, m, test)?.ty(self.ty))
     }
 
     pub fn same(&self, other: &Self) -> bool {
@@ -246,14 +345,18 @@ impl Default for KernelTrace {
     }
 }
 
-impl Merge for KernelTrace {
-    fn merge(&mut self, _: &Self) {
-        todo!()
-    }
+impl KernelTrace {
+    /// Merge the trace of the same kernel recorded by another run
+    pub fn merge_owned(&mut self, other: Self) -> error::Result<()> {
+        if self.ty != other.ty {
+            return Err(Error::Align(format!(
+                "kernel {} is merged with kernel {}",
+                self.ty, other.ty
+            )));
+        }
 
-    fn merge_own(&mut self, other: Self) {
-        assert_eq!(self.ty, other.ty);
         self.g.merge(other.g);
+        Ok(())
     }
 }
 
@@ -277,11 +380,11 @@ fn convert_raw_dcfg(g: RawDCFG) -> TDcfg {
             .map(|n| {
                 (
                     n.id,
-                    Node {
-                        id: n.id,
-                        cf: CfMatrix::from_raw(n.control_flow),
-                        mem_access: MemAccessRecord::from(n.mem_access),
-                    },
+                    Node::new(
+                        n.id,
+                        CfMatrix::from_raw(n.control_flow),
+                        MemAccessRecord::from(n.mem_access),
+                    ),
                 )
             })
             .collect::<BTreeMap<_, _>>(),
@@ -290,20 +393,128 @@ fn convert_raw_dcfg(g: RawDCFG) -> TDcfg {
 
 #[cfg(test)]
 mod test {
-    use std::io::BufReader;
+    use std::{collections::BTreeMap, io::BufReader};
 
     use monitor::raw::RawData;
     use serde_json;
 
-    use super::convert_raw_dcfg;
+    use super::{convert_raw_dcfg, Trace};
+    use crate::{
+        cost::Coalescing,
+        fixture::Kernel,
+        granularity::Granularity,
+        memory::{MemAccessInstr, TargetAddr},
+    };
+
+    /// First instruction of `kernel` once converted
+    fn convert(
+        kernel: &Kernel,
+        granularity: &Granularity,
+        coalescing: &Coalescing,
+    ) -> MemAccessInstr {
+        let mut trace = Trace::from_raw(kernel.raw(), granularity, coalescing).unwrap();
+        let node = trace.kernels[0].trace.g.nodes.remove(&0).unwrap();
+        node.mem_access.instrs.into_iter().next().unwrap()
+    }
+
+    /// Addresses accessed by the first instruction of `kernel` once converted
+    fn accessed(kernel: &Kernel, granularity: &Granularity) -> Vec<TargetAddr> {
+        let instr = convert(kernel, granularity, &Coalescing::default());
+        instr.data[0].keys().copied().collect()
+    }
 
     #[test]
     pub fn test_convert_raw_dcfg() {
         let reader = BufReader::new(std::fs::File::open("../examples/kernel.json").unwrap());
         let data: RawData = serde_json::from_reader(reader).unwrap();
 
-        let mut ks = data.get_kernel();
+        let mut ks = data.get_kernel().unwrap();
         let g = ks.pop().unwrap().g;
         convert_raw_dcfg(g);
     }
+
+    #[test]
+    pub fn test_granularity() {
+        // a global access 8 bytes into the pool
+        let kernel = Kernel::new()
+            .pool(0x1000, 4096)
+            .node(1)
+            .access("GLOBAL", &[(0x1008, 4)]);
+        let offsets = |granularity| -> Vec<_> {
+            accessed(&kernel, &granularity)
+                .iter()
+                .map(|addr| addr.offset)
+                .collect()
+        };
+
+        // the same cache line
+        assert_eq!(offsets(Granularity::default()), vec![0]);
+        assert_eq!(offsets(Granularity::exact()), vec![8]);
+    }
+
+    #[test]
+    pub fn test_pool_identity() {
+        // the second of two buffers allocated at another base in every run
+        let addrs = |base: u64| {
+            let kernel = Kernel::new()
+                .pool(base, 0x1000)
+                .pool(base + 0x1000, 0x1000)
+                .node(1)
+                .access("GLOBAL", &[(base + 0x1010, 4)]);
+            accessed(&kernel, &Granularity::exact())
+        };
+
+        let (l, r) = (addrs(0x10000), addrs(0x20000));
+        assert_eq!(l, r);
+        assert_eq!((l[0].offset, l[0].pool.unwrap().order), (0x10, 1));
+    }
+
+    #[test]
+    pub fn test_buffers() {
+        // a 4 KiB buffer allocated in `main` at 0x10000
+        let kernel = Kernel::new()
+            .pool(0x10000, 4096)
+            .alloc(
+                0x10000,
+                4096,
+                &[("cudaMalloc", "", 0), ("main", "aes.cu", 120)],
+            )
+            .node(1)
+            .access("GLOBAL", &[(0x10080, 4)]);
+        let trace =
+            Trace::from_raw(kernel.raw(), &Granularity::exact(), &Coalescing::default()).unwrap();
+
+        let addr = accessed(&kernel, &Granularity::exact())[0];
+        assert_eq!(
+            trace.buffers[&addr.pool.unwrap()].to_string(),
+            "buffer allocated at aes.cu:120 (4 KiB)"
+        );
+    }
+
+    #[test]
+    pub fn test_coalescing() {
+        let transactions = |warps: &[(&[u64], usize)], coalescing| {
+            let kernel = Kernel::new()
+                .pool(0x1000, 0x1000)
+                .node(1)
+                .warps("GLOBAL", warps);
+            convert(&kernel, &Granularity::default(), &coalescing).transactions
+        };
+
+        // the same words of a cache line on both sides, every warp access touches one sector
+        // on the fixed side and two on the random one
+        let fix = [(&[0x1000, 0x1004][..], 2), (&[0x1020, 0x1024][..], 2)];
+        let rnd = [(&[0x1000, 0x1020][..], 2), (&[0x1004, 0x1024][..], 2)];
+        let (l, r) = (
+            transactions(&fix, Coalescing::default()),
+            transactions(&rnd, Coalescing::default()),
+        );
+        assert_eq!(l.data, vec![BTreeMap::from([(1, 4)])]);
+        assert_eq!(r.data, vec![BTreeMap::from([(2, 4)])]);
+        assert_eq!((l.runs.mean(), r.runs.mean()), (4.0, 8.0));
+
+        // one transaction per line
+        let line = Coalescing { sector: 128 };
+        assert_eq!(transactions(&rnd, line).data, transactions(&fix, line).data);
+    }
 }
@@ -1,6 +1,9 @@
-use std::{fs::File, io::BufReader};
+use std::{fs::File, io::BufReader, path::Path};
 
-use crate::raw::{RawAlloc, RawCsFrame, RawData, RawKernelTrace, RawTrace};
+use crate::{
+    raw::{RawAlloc, RawAllocContext, RawData, RawKernelTrace, RawTrace},
+    Error, Result,
+};
 
 // pub struct Acceptor {
 //     p1: String,
@@ -64,54 +67,75 @@ impl DataAcceptor {
         }
     }
 
-    pub fn kernel(&self) -> Vec<RawKernelTrace> {
-        let file = File::open(format!("{}/kernel.json", self.path)).unwrap();
-        let reader = BufReader::new(file);
-        let data = serde_json::from_reader(reader).unwrap();
-        if let RawData::Kernel(d) = data {
-            // d.iter().for_each(|k| {
-            //     self.kernels.insert(k.ty, k.name.clone());
-            // });
-            d
-        } else {
-            panic!()
+    pub fn kernel(&self) -> Result<Vec<RawKernelTrace>> {
+        match self.read("kernel.json")? {
+            (RawData::Kernel(d), _) => Ok(d),
+            (_, path) => Err(Error::Schema {
+                path,
+                expect: "kernel",
+            }),
         }
     }
 
-    pub fn context(&self) -> Vec<Vec<RawCsFrame>> {
-        let file = File::open(format!("{}/context.json", self.path)).unwrap();
-        let reader = BufReader::new(file);
-        let data = serde_json::from_reader(reader).unwrap();
-        if let RawData::Context(d) = data {
-            d
-        } else {
-            panic!()
+    /// Contexts are optional, an empty list is returned without `context.json`
+    pub fn context(&self) -> Result<Vec<RawAllocContext>> {
+        if !Path::new(&self.path).join("context.json").exists() {
+            return Ok(Vec::new());
+        }
+
+        match self.read("context.json")? {
+            (RawData::Context(d), _) => Ok(d),
+            (_, path) => Err(Error::Schema {
+                path,
+                expect: "context",
+            }),
         }
     }
 
-    pub fn alloc(&self) -> Vec<RawAlloc> {
-        if let Ok(file) = File::open(format!("{}/alloc.json", self.path)) {
-            let reader = BufReader::new(file);
-            let data = serde_json::from_reader(reader).unwrap();
-            if let RawData::Alloc(d) = data {
-                d
-            } else {
-                panic!()
-            }
-        } else {
-            Vec::new()
+    /// Allocations are optional, an empty list is returned without `alloc.json`
+    pub fn alloc(&self) -> Result<Vec<RawAlloc>> {
+        if !Path::new(&self.path).join("alloc.json").exists() {
+            return Ok(Vec::new());
+        }
+
+        match self.read("alloc.json")? {
+            (RawData::Alloc(d), _) => Ok(d),
+            (_, path) => Err(Error::Schema {
+                path,
+                expect: "alloc",
+            }),
         }
     }
 
-    pub fn raw_trace(&self) -> RawTrace {
+    /// Allocations and contexts only name buffers, unreadable ones are left out with a warning
+    pub fn raw_trace(&self) -> Result<RawTrace> {
         let trace = RawTrace {
-            kernels: self.kernel(),
-            // context: self.context(),
-            // alloc: self.alloc(),
+            kernels: self.kernel()?,
+            context: self.context().unwrap_or_else(|err| {
+                log::warn!("{err}, allocation contexts ignored");
+                Vec::new()
+            }),
+            alloc: self.alloc().unwrap_or_else(|err| {
+                log::warn!("{err}, allocations ignored");
+                Vec::new()
+            }),
         };
 
         // println!("{:?}", trace);
 
-        trace
+        Ok(trace)
+    }
+
+    fn read(&self, file: &str) -> Result<(RawData, String)> {
+        let path = format!("{}/{file}", self.path.trim_end_matches('/'));
+        let reader = match File::open(&path) {
+            Ok(file) => BufReader::new(file),
+            Err(source) => return Err(Error::Io { path, source }),
+        };
+
+        match serde_json::from_reader(reader) {
+            Ok(data) => Ok((data, path)),
+            Err(source) => Err(Error::Parse { path, source }),
+        }
     }
 }
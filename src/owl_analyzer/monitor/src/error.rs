@@ -0,0 +1,37 @@
+use std::fmt;
+
+/// Errors of reading a trace dumped by the monitor
+#[derive(Debug)]
+pub enum Error {
+    /// The trace file can't be opened or read
+    Io { path: String, source: std::io::Error },
+    /// The trace file is not valid JSON of the expected shape
+    Parse {
+        path: String,
+        source: serde_json::Error,
+    },
+    /// The trace file holds another kind of data than expected
+    Schema { path: String, expect: &'static str },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "failed to read `{path}`: {source}"),
+            Error::Parse { path, source } => write!(f, "failed to parse `{path}`: {source}"),
+            Error::Schema { path, expect } => write!(f, "`{path}` is not {expect} data"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Parse { source, .. } => Some(source),
+            Error::Schema { .. } => None,
+        }
+    }
+}
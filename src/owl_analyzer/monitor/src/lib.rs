@@ -1,4 +1,6 @@
 mod acceptor;
 pub mod cuda;
+mod error;
 pub mod raw;
 pub use acceptor::DataAcceptor;
+pub use error::{Error, Result};
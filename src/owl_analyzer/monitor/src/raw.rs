@@ -14,33 +14,38 @@ pub struct RawDirect {
 pub struct RawTrace {
     // pub datas: Vec<RawData>
     pub kernels: Vec<RawKernelTrace>,
-    // pub context: Vec<Vec<RawCsFrame>>,
-    // pub alloc: Vec<RawAlloc>,
+    /// Host call stack of the allocations, by allocated address
+    #[serde(default)]
+    pub context: Vec<RawAllocContext>,
+    /// Host side device allocations, in allocation order
+    #[serde(default)]
+    pub alloc: Vec<RawAlloc>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type", content = "data")]
 pub enum RawData {
     Alloc(Vec<RawAlloc>),
-    Context(Vec<Vec<RawCsFrame>>),
+    Context(Vec<RawAllocContext>),
     Kernel(Vec<RawKernelTrace>),
 }
 
 impl RawData {
-    pub fn get_kernel(self) -> Vec<RawKernelTrace> {
-        let a = if let RawData::Kernel(kernels) = self {
-            kernels
+    pub fn get_kernel(self) -> Option<Vec<RawKernelTrace>> {
+        if let RawData::Kernel(kernels) = self {
+            Some(kernels)
         } else {
-            panic!("Not kernel")
-        };
-        a
+            None
+        }
     }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RawAlloc {
     pub addr: u64,
-    // pub name: String,
+    /// Allocating function, e.g. `cudaMalloc`
+    #[serde(default)]
+    pub name: String,
     pub size: u64,
 }
 
@@ -57,6 +62,13 @@ pub struct RawCsFrame {
 // #[derive(Debug, Dser)]
 pub type RawContext = Vec<RawCsFrame>;
 
+/// Host call stack of the allocation returning `addr`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RawAllocContext {
+    pub addr: u64,
+    pub bt: RawContext,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct RawKernelTrace {
     pub id: KernelId,
@@ -118,6 +130,17 @@ pub struct RawMemAccessWithType {
     pub memory: Vec<RawMemAccess>,
     #[serde(rename = "type")]
     pub ty: MemType,
+    /// Every distinct warp access, empty in traces of monitors not recording them
+    #[serde(default)]
+    pub warps: Vec<RawWarpAccess>,
+}
+
+/// Addresses accessed by the active threads of a single warp access
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RawWarpAccess {
+    pub addr: Vec<u64>,
+    /// Times the same warp access is recorded
+    pub count: usize,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -0,0 +1,362 @@
+use std::{collections::BTreeMap, io, path::Path, time::Duration};
+
+use analyzer::{
+    Adaptive, Calibration, Coalescing, Correction, Granularity, KernelFilter, Monitor, Permutation,
+    ReportFormat, ScheduleMode, TestMethod,
+};
+use serde::{Deserialize, Serialize};
+
+/// Configuration of a campaign, saved next to the runs of every command when they are recorded
+/// and updated with the report settings next to every report
+pub const CAMPAIGN_FILE: &str = "campaign.json";
+
+/// Settings deciding what runs are recorded, a report keeps the recorded ones
+const RECORDING: &[&str] = &[
+    "rand_cmd",
+    "times",
+    "fix_times",
+    "rnd_times",
+    "calibration_times",
+    "schedule",
+    "seed",
+    "adaptive_max",
+    "adaptive_step",
+    "adaptive_band",
+    "pipe_path",
+    "env",
+];
+
+/// Campaign configuration, loaded from a TOML or JSON file and overridden by command line flags
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Campaign {
+    /// fixed input commands
+    pub cmds: Vec<String>,
+    /// random input command
+    pub rand_cmd: Option<String>,
+    /// runs of each input class
+    pub times: usize,
+    /// fixed input runs, default `times`
+    pub fix_times: Option<usize>,
+    /// random input runs, default `times`
+    pub rnd_times: Option<usize>,
+    /// sign level (0, 1)
+    pub sign: Option<f64>,
+    /// multiple-testing correction over every tested site
+    pub correction: Correction,
+    /// test comparing every control flow and data flow site
+    pub method: TestMethod,
+    /// label permutations of every site, empirical p values if set
+    pub permutations: Option<usize>,
+    /// bootstrap resamples of the leaked bits
+    pub bootstrap: usize,
+    /// how sites differing between fixed input runs are reported
+    pub calibration: Calibration,
+    /// runs of a second identical fixed input, halves of the fixed input runs if zero
+    pub calibration_times: usize,
+    /// result root, default `$OWL_RES` or `./owl_results`
+    pub res_root: Option<String>,
+    pub schedule: ScheduleMode,
+    pub seed: Option<u64>,
+    pub adaptive_max: Option<usize>,
+    pub adaptive_step: Option<usize>,
+    pub adaptive_band: f64,
+    /// passed to the monitor as `OWL_PIPE2`
+    pub pipe_path: Option<String>,
+    /// extra monitor environment of every execution
+    pub env: BTreeMap<String, String>,
+    pub jobs: usize,
+    /// seconds
+    pub timeout: Option<f64>,
+    pub retries: usize,
+    pub resume: bool,
+    /// abort the campaign on a bad trace instead of skipping the run or command
+    pub abort_on_bad_run: bool,
+    pub kernels: KernelFilter,
+    /// unit accessed addresses of every memory type are compared in
+    pub granularity: Granularity,
+    /// sector size global memory transactions are counted in
+    pub coalescing: Coalescing,
+    /// formats every report is written in
+    pub formats: Vec<ReportFormat>,
+}
+
+impl Default for Campaign {
+    fn default() -> Self {
+        Self {
+            cmds: Vec::new(),
+            rand_cmd: None,
+            times: 2,
+            fix_times: None,
+            rnd_times: None,
+            sign: None,
+            correction: Correction::None,
+            method: TestMethod::default(),
+            permutations: None,
+            bootstrap: 0,
+            calibration: Calibration::None,
+            calibration_times: 0,
+            res_root: None,
+            schedule: ScheduleMode::default(),
+            seed: None,
+            adaptive_max: None,
+            adaptive_step: None,
+            adaptive_band: 2.0,
+            pipe_path: None,
+            env: BTreeMap::new(),
+            jobs: 1,
+            timeout: None,
+            retries: 0,
+            resume: false,
+            abort_on_bad_run: false,
+            kernels: KernelFilter::default(),
+            granularity: Granularity::default(),
+            coalescing: Coalescing::default(),
+            formats: vec![ReportFormat::Json],
+        }
+    }
+}
+
+impl Campaign {
+    /// Load a `.toml` file, any other extension is read as JSON
+    pub fn load(path: &str) -> io::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+
+        Self::parse(
+            &content,
+            Path::new(path).extension().and_then(|ext| ext.to_str()),
+        )
+    }
+
+    /// Parse the content of a file with extension `ext`
+    pub fn parse(content: &str, ext: Option<&str>) -> io::Result<Self> {
+        match ext {
+            Some("toml") => {
+                toml::from_str(content).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            _ => Ok(serde_json::from_str(content)?),
+        }
+    }
+
+    /// The campaign reporting the runs recorded by `recorded`, its recording settings are
+    /// replaced by the recorded ones, return the names of the settings given here that differ
+    pub fn reported(&self, recorded: &Campaign) -> io::Result<(Campaign, Vec<String>)> {
+        let (mut merged, recorded, default) = (
+            serde_json::to_value(self)?,
+            serde_json::to_value(recorded)?,
+            serde_json::to_value(Campaign::default())?,
+        );
+
+        let mut conflicts = Vec::new();
+        for key in RECORDING.iter().copied().chain(["cmds"]) {
+            // commands are recorded one per directory, they never match all of the campaign
+            if key != "cmds" && merged[key] != default[key] && merged[key] != recorded[key] {
+                conflicts.push(key.to_owned());
+            }
+            merged[key] = recorded[key].clone();
+        }
+
+        Ok((serde_json::from_value(merged)?, conflicts))
+    }
+
+    pub fn res_root(&self) -> String {
+        self.res_root
+            .clone()
+            .or_else(|| std::env::var("OWL_RES").ok())
+            .unwrap_or_else(|| "./owl_results".to_string())
+    }
+
+    /// Significance level of the report, 2.0 reports every site, None if the settings are invalid
+    pub fn threshold(&self) -> Option<f64> {
+        let mut threshold = 2.0;
+
+        // update threshold
+        if let Some(sign) = self.sign {
+            if !(0.0..=1.0).contains(&sign) {
+                log::error!("sign must > 0 and < 1");
+                return None;
+            }
+            threshold = 1.0 - sign;
+        } else if self.adaptive_max.is_some() {
+            // every site is decided at once without a significance level
+            log::error!("adaptive recording needs a sign level, set `--sign` or `sign`");
+            return None;
+        }
+
+        Some(threshold)
+    }
+
+    pub fn fix_times(&self) -> usize {
+        self.fix_times.unwrap_or(self.times)
+    }
+
+    pub fn rnd_times(&self) -> usize {
+        self.rnd_times.unwrap_or(self.times)
+    }
+
+    pub fn adaptive(&self) -> Option<Adaptive> {
+        self.adaptive_max.map(|max| Adaptive {
+            step: self.adaptive_step.unwrap_or(self.times),
+            max,
+            band: self.adaptive_band,
+        })
+    }
+
+    /// Resampling of the recorded runs, `seed` and `jobs` are shared with the recording
+    pub fn permutation(&self) -> Option<Permutation> {
+        (self.permutations.is_some() || self.bootstrap > 0).then(|| Permutation {
+            rounds: self.permutations.unwrap_or_default(),
+            bootstrap: self.bootstrap,
+            seed: self.seed,
+            jobs: self.jobs.max(1),
+        })
+    }
+
+    pub fn monitor(&self) -> Monitor {
+        let mut envs: Vec<_> = self
+            .env
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+
+        if let Some(pipe) = &self.pipe_path {
+            envs.push(("OWL_PIPE2".to_owned(), pipe.clone()));
+        }
+
+        Monitor {
+            envs,
+            jobs: self.jobs.max(1),
+            timeout: self.timeout.map(Duration::from_secs_f64),
+            retries: self.retries,
+            resume: self.resume,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use analyzer::{Bucket, Calibration, Correction, ReportFormat, ScheduleMode, TestMethod};
+
+    use super::Campaign;
+
+    #[test]
+    pub fn test_load_campaign() {
+        let campaign = Campaign::parse(
+            r#"
+cmds = ["./aes 0", "./aes 1"]
+rand_cmd = "./aes rnd"
+times = 10
+rnd_times = 40
+sign = 0.95
+correction = "holm"
+method = "chi2"
+permutations = 1000
+calibration = "mark"
+schedule = "interleaved"
+formats = ["json", "csv"]
+
+[env]
+OWL_FILE = "/tmp/owl"
+
+[kernels]
+include = ["aes"]
+
+[granularity]
+shared = "byte"
+
+[coalescing]
+sector = 128
+"#,
+            Some("toml"),
+        )
+        .unwrap();
+        assert_eq!(campaign.cmds.len(), 2);
+        assert_eq!((campaign.fix_times(), campaign.rnd_times()), (10, 40));
+        assert_eq!(campaign.schedule, ScheduleMode::Interleaved);
+        assert_eq!(campaign.correction, Correction::Holm);
+        assert_eq!(campaign.method, TestMethod::Chi2);
+        let permutation = campaign.permutation().unwrap();
+        assert_eq!((permutation.rounds, permutation.bootstrap), (1000, 0));
+        assert_eq!(campaign.calibration, Calibration::Mark);
+        assert_eq!(
+            campaign.formats,
+            vec![ReportFormat::Json, ReportFormat::Csv]
+        );
+        assert!(!campaign.kernels.is_tested("sha_kernel"));
+        // unset memory types keep their default
+        assert_eq!(campaign.granularity.shared, Bucket::Byte);
+        assert_eq!(campaign.granularity.global, Bucket::Line);
+        assert_eq!(campaign.coalescing.sector, 128);
+        assert!((campaign.threshold().unwrap() - 0.05).abs() < 1e-9);
+        assert_eq!(campaign.monitor().envs.len(), 1);
+        // unset keys keep their default
+        assert_eq!(campaign.jobs, 1);
+
+        // the saved effective configuration loads back the same
+        let json = serde_json::to_string(&campaign).unwrap();
+        assert_eq!(Campaign::parse(&json, Some("json")).unwrap(), campaign);
+
+        assert!(Campaign::parse("unknown_key = 1", Some("toml")).is_err());
+    }
+
+    #[test]
+    pub fn test_threshold() {
+        assert_eq!(Campaign::default().threshold(), Some(2.0));
+
+        let adaptive = Campaign {
+            adaptive_max: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(adaptive.threshold(), None);
+        let campaign = Campaign {
+            sign: Some(0.9),
+            ..adaptive
+        };
+        assert!((campaign.threshold().unwrap() - 0.1).abs() < 1e-9);
+        assert!(campaign.adaptive().is_some());
+
+        let campaign = Campaign {
+            sign: Some(1.5),
+            ..Default::default()
+        };
+        assert_eq!(campaign.threshold(), None);
+    }
+
+    #[test]
+    pub fn test_reported_campaign() {
+        let recorded = Campaign {
+            cmds: vec!["./aes 0".to_owned()],
+            rand_cmd: Some("./aes rnd".to_owned()),
+            times: 10,
+            seed: Some(3),
+            ..Default::default()
+        };
+
+        // report settings are kept, recording settings left to default are taken as recorded
+        let campaign = Campaign {
+            cmds: vec!["./aes 0".to_owned(), "./aes 1".to_owned()],
+            sign: Some(0.9),
+            method: TestMethod::Chi2,
+            ..Default::default()
+        };
+        let (reported, conflicts) = campaign.reported(&recorded).unwrap();
+        assert!(conflicts.is_empty());
+        assert_eq!(reported.cmds, recorded.cmds);
+        assert_eq!((reported.times, reported.seed), (10, Some(3)));
+        assert_eq!(
+            (reported.sign, reported.method),
+            (Some(0.9), TestMethod::Chi2)
+        );
+
+        // a recording setting given differently can't change the recorded runs
+        let campaign = Campaign {
+            times: 4,
+            schedule: ScheduleMode::Interleaved,
+            ..Default::default()
+        };
+        let (reported, conflicts) = campaign.reported(&recorded).unwrap();
+        assert_eq!(conflicts, vec!["times", "schedule"]);
+        assert_eq!(reported.times, 10);
+        assert_eq!(reported.schedule, ScheduleMode::default());
+    }
+}
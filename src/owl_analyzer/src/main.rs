@@ -1,190 +1,420 @@
-use analyzer::{get_trace, Analyzer, Report, Trace};
-use clap::Parser;
-use std::io::{self, Read, Write};
+use analyzer::{Calibration, Correction, ReportFormat, ScheduleMode, TestMethod};
+use clap::{Args, Parser, Subcommand};
+use config::Campaign;
+use std::io::{self, Read};
+
+mod config;
+mod stage;
 
 #[derive(Parser)]
 struct Cli {
     /// Log level(0: Off, 1: Error, 2: Warn, 3: Info, 4: Debug, 5: Trace), default level 3
-    #[clap(short, long, default_value = "3")]
+    #[clap(short, long, default_value = "3", global = true)]
     log: u8,
-    /// Pipe path
-    #[clap(short, long)]
-    pipe_path: Option<String>,
+    /// Result root, default `$OWL_RES` or `./owl_results`
+    #[clap(long, global = true)]
+    res_root: Option<String>,
+    /// Campaign configuration file (`.toml` or `.json`), overridden by command line flags
+    #[clap(long, global = true)]
+    config: Option<String>,
+    /// Abort on a bad trace instead of skipping the run or command
+    #[clap(long, global = true)]
+    abort_on_bad_run: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Stage 1: record one trace of every command to `<res_root>/stage1`
+    Record {
+        #[command(flatten)]
+        cmds: CmdArgs,
+        #[command(flatten)]
+        monitor: MonitorArgs,
+    },
+    /// Stage 2: drop commands whose stage 1 trace is the same as another one
+    Dedup,
+    /// Stage 3: record fixed and random input traces of the selected commands
+    Test {
+        /// commands to test, default the `cmds` of the config, the ones selected by stage 2 if
+        /// stage 1 recorded them
+        #[command(flatten)]
+        cmds: CmdArgs,
+        #[command(flatten)]
+        test: TestArgs,
+        /// significance level used to decide sites in adaptive mode
+        #[command(flatten)]
+        report: ReportArgs,
+        #[command(flatten)]
+        monitor: MonitorArgs,
+    },
+    /// Test recorded traces for leakage and write `report.json`
+    Report(ReportArgs),
+    /// Run all stages
+    Run {
+        #[command(flatten)]
+        cmds: CmdArgs,
+        #[command(flatten)]
+        test: TestArgs,
+        #[command(flatten)]
+        report: ReportArgs,
+        #[command(flatten)]
+        monitor: MonitorArgs,
+    },
+}
+
+#[derive(Args)]
+struct CmdArgs {
     /// Commands file
     #[clap(long)]
     cmds_file: Option<String>,
     /// commands list, separate by `:`
     #[clap(short, long)]
     cmds: Option<String>,
-    /// sign level (0, 1)
-    #[clap(short, long)]
-    sign: Option<f64>,
-    /// leakage test times > 0
-    #[clap(short, long)]
-    test_times: Option<usize>,
-    /// leakage test rand cmd
-    #[clap(short, long)]
-    rand_cmd: Option<String>,
     /// test command
     #[arg(last = true)]
     cmd: Vec<String>,
 }
 
-#[tokio::main]
-async fn main() -> io::Result<()> {
-    let cli = Cli::parse();
-
-    if std::env::var("RUST_LOG").is_err() {
-        env_logger::builder()
-            .filter_level(match cli.log {
-                0 => log::LevelFilter::Off,
-                1 => log::LevelFilter::Error,
-                2 => log::LevelFilter::Warn,
-                3 => log::LevelFilter::Info,
-                4 => log::LevelFilter::Debug,
-                5 => log::LevelFilter::Trace,
-                _ => log::LevelFilter::Info,
-            })
-            .init();
-    } else {
-        env_logger::init();
+impl CmdArgs {
+    fn is_empty(&self) -> bool {
+        self.cmd.is_empty() && self.cmds.is_none() && self.cmds_file.is_none()
     }
 
-    let mut cmds = Vec::default();
+    fn collect(&self) -> io::Result<Vec<String>> {
+        let mut cmds = Vec::default();
 
-    let mut threshold = 2.0;
-
-    // update threshold
-    if let Some(sign) = cli.sign {
-        if sign < 0.0 || sign > 1.0 {
-            log::error!("sign must > 0 and < 1");
-            return Ok(());
+        // last command
+        let cmd = self.cmd.join(" ");
+        if !cmd.is_empty() {
+            cmds.push(cmd)
         }
-        threshold = 1.0 - sign;
-    }
 
-    // last command
-    let cmd = cli.cmd.join(" ");
-    if !cmd.is_empty() {
-        cmds.push(cmd.as_str())
-    }
+        if let Some(c_str) = &self.cmds {
+            cmds.extend(c_str.split(':').map(String::from));
+        }
 
-    if let Some(c_str) = &cli.cmds {
-        cmds.extend(c_str.split(':'));
-    }
+        if let Some(f_path) = &self.cmds_file {
+            let mut content = String::new();
+            std::fs::File::open(f_path)?.read_to_string(&mut content)?;
+            cmds.extend(
+                content
+                    .split('\n')
+                    .filter(|slice| !slice.is_empty())
+                    .map(String::from),
+            );
+        }
 
-    let mut content = String::new();
-    let _ = cli.cmds_file.map(|f_path| {
-        std::fs::File::open(f_path)
-            .and_then(|mut f| f.read_to_string(&mut content))
-            .unwrap();
-        cmds.extend(content.split('\n').filter(|slice| !slice.is_empty()));
-    });
+        log::debug!("cmds content: {:?}", cmds);
 
-    log::debug!("cmds content: {:?}", cmds);
+        if cmds.is_empty() {
+            log::error!("cmds is empty");
+        }
 
-    if cmds.is_empty() {
-        log::error!("cmds is empty");
+        Ok(cmds)
     }
 
-    log::info!("--- Finishing trace ---");
-
-    let mut res_root = "./owl_results".to_string();
-    if let Ok(path) = std::env::var("OWL_RES") {
-        res_root = path;
-    }
+    fn apply(&self, campaign: &mut Campaign) -> io::Result<()> {
+        if !self.is_empty() {
+            campaign.cmds = self.collect()?;
+        }
 
-    if cli.rand_cmd.is_none() {
-        log::error!("--rand-cmd required for leakage test");
-        return Ok(());
+        Ok(())
     }
+}
 
-    let rnd_cmd = cli.rand_cmd.as_ref().unwrap();
-
-    log::info!("Leakage test start");
-
-    let filted = if cmds.len() > 1 {
-        let traces = stage1(&cmds, &format!("{res_root}/stage1"));
-
-        stage2(&cmds, traces)
-    } else {
-        log::warn!("Only one command, skip stage 1 and 2");
-        cmds
-    };
-
-    stage3(
-        filted,
-        rnd_cmd,
-        &res_root,
-        cli.test_times.unwrap_or(2),
-        threshold,
-    );
+#[derive(Args)]
+struct MonitorArgs {
+    /// Pipe path, passed to the monitor as `OWL_PIPE2`
+    #[clap(short, long)]
+    pipe_path: Option<String>,
+    /// Extra monitor environment `KEY=VALUE` of every execution
+    #[clap(short, long)]
+    env: Vec<String>,
+    /// Maximum number of executions recorded at the same time, default 1
+    #[clap(short, long)]
+    jobs: Option<usize>,
+    /// Kill an execution running longer than this many seconds
+    #[clap(long)]
+    timeout: Option<f64>,
+    /// Execute a crashed run or a run without trace again at most this many times, default 0
+    #[clap(long)]
+    retries: Option<usize>,
+    /// Skip runs and commands completed by a previous campaign
+    #[clap(long)]
+    resume: bool,
+}
 
-    log::info!("Analyze finished");
+impl MonitorArgs {
+    fn apply(self, campaign: &mut Campaign) {
+        for kv in self.env {
+            match kv.split_once('=') {
+                Some((k, v)) => {
+                    campaign.env.insert(k.to_owned(), v.to_owned());
+                }
+                None => log::warn!("ignore env `{kv}`, expect `KEY=VALUE`"),
+            }
+        }
 
-    Ok(())
+        if self.pipe_path.is_some() {
+            campaign.pipe_path = self.pipe_path;
+        }
+        if let Some(jobs) = self.jobs {
+            campaign.jobs = jobs;
+        }
+        if self.timeout.is_some() {
+            campaign.timeout = self.timeout;
+        }
+        if let Some(retries) = self.retries {
+            campaign.retries = retries;
+        }
+        campaign.resume |= self.resume;
+    }
 }
 
-pub fn leakage_test(
-    trace_path: &str,
-    cmd: &str,
-    rand_cmd: &str,
-    times: usize,
-    threshold: f64,
-) -> Report {
-    let mut analyzer = Analyzer {
-        // pipe_path: pipe_path.to_owned(),
-        fix_cmd: cmd.to_owned(),
-        rnd_cmd: rand_cmd.to_owned(),
-        times,
-        threshold,
-
-        trace_path: trace_path.to_owned(),
-        kernels: Default::default(),
-    };
-
-    analyzer.test()
+#[derive(Args)]
+struct TestArgs {
+    /// leakage test times > 0, default 2
+    #[clap(short, long)]
+    test_times: Option<usize>,
+    /// fixed input runs, default `--test-times`
+    #[clap(long)]
+    fix_times: Option<usize>,
+    /// random input runs, default `--test-times`
+    #[clap(long)]
+    rnd_times: Option<usize>,
+    /// leakage test rand cmd
+    #[clap(short, long)]
+    rand_cmd: Option<String>,
+    /// execution order of fixed and random input runs (sequential, interleaved)
+    #[clap(long)]
+    schedule: Option<ScheduleMode>,
+    /// seed of the interleaved schedule, random if not set
+    #[clap(long)]
+    seed: Option<u64>,
+    /// keep adding runs until every tested site is decided, at most this many runs per input,
+    /// needs `--sign`
+    #[clap(long)]
+    adaptive_max: Option<usize>,
+    /// runs added per input in every adaptive round, default `--test-times`
+    #[clap(long)]
+    adaptive_step: Option<usize>,
+    /// p below 1 in [threshold / band, threshold * band) is undecided in adaptive mode, default 2.0
+    #[clap(long)]
+    adaptive_band: Option<f64>,
+    /// runs of a second identical fixed input for the calibration
+    #[clap(long)]
+    calibration_times: Option<usize>,
 }
 
-fn stage1(cmds: &Vec<&str>, trace_path: &str) -> Vec<Trace> {
-    log::info!("Stage 1 start");
-    cmds.iter()
-        .enumerate()
-        .map(|(idx, cmd)| get_trace(cmd, &format!("{trace_path}/{idx}")))
-        .collect()
+impl TestArgs {
+    fn apply(self, campaign: &mut Campaign) {
+        if let Some(times) = self.test_times {
+            campaign.times = times;
+        }
+        if self.fix_times.is_some() {
+            campaign.fix_times = self.fix_times;
+        }
+        if self.rnd_times.is_some() {
+            campaign.rnd_times = self.rnd_times;
+        }
+        if self.rand_cmd.is_some() {
+            campaign.rand_cmd = self.rand_cmd;
+        }
+        if let Some(schedule) = self.schedule {
+            campaign.schedule = schedule;
+        }
+        if self.seed.is_some() {
+            campaign.seed = self.seed;
+        }
+        if self.adaptive_max.is_some() {
+            campaign.adaptive_max = self.adaptive_max;
+        }
+        if self.adaptive_step.is_some() {
+            campaign.adaptive_step = self.adaptive_step;
+        }
+        if let Some(band) = self.adaptive_band {
+            campaign.adaptive_band = band;
+        }
+        if let Some(times) = self.calibration_times {
+            campaign.calibration_times = times;
+        }
+    }
 }
 
-fn stage2<'a>(cmds: &Vec<&'a str>, mut traces: Vec<Trace>) -> Vec<&'a str> {
-    log::info!("Stage 2 start");
-    let mut filted = Vec::new();
-    filted.push((traces.pop().unwrap(), 0usize));
+#[derive(Args)]
+struct ReportArgs {
+    /// sign level (0, 1)
+    #[clap(short, long)]
+    sign: Option<f64>,
+    /// multiple-testing correction over every tested site (none, bonferroni, holm, bh)
+    #[clap(long)]
+    correction: Option<Correction>,
+    /// test comparing every site (ks, chi2, g, ad, welch)
+    #[clap(long)]
+    method: Option<TestMethod>,
+    /// label permutations of every site, report empirical p values
+    #[clap(long)]
+    permutations: Option<usize>,
+    /// bootstrap resamples of the bits leaked by every site
+    #[clap(long)]
+    bootstrap: Option<usize>,
+    /// sites differing between fixed input runs (none, mark, suppress)
+    #[clap(long)]
+    calibration: Option<Calibration>,
+    /// test only kernels whose name contains one of these patterns
+    #[clap(long)]
+    include_kernel: Vec<String>,
+    /// never test kernels whose name contains one of these patterns
+    #[clap(long)]
+    exclude_kernel: Vec<String>,
+    /// report formats (json, csv), default json
+    #[clap(short, long)]
+    format: Vec<ReportFormat>,
+}
 
-    traces.into_iter().enumerate().for_each(|(idx, trace)| {
-        let idx = idx + 1;
-        if let Some((_, same_idx)) = filted.iter().find(|(t2, _)| t2.same(&trace)) {
-            log::info!("Find same trace, {idx} and {same_idx}")
-        } else {
-            filted.push((trace, idx));
+impl ReportArgs {
+    fn apply(self, campaign: &mut Campaign) {
+        if self.sign.is_some() {
+            campaign.sign = self.sign;
         }
-    });
-
-    filted.into_iter().map(|(_, idx)| cmds[idx]).collect()
+        if let Some(correction) = self.correction {
+            campaign.correction = correction;
+        }
+        if let Some(method) = self.method {
+            campaign.method = method;
+        }
+        if self.permutations.is_some() {
+            campaign.permutations = self.permutations;
+        }
+        if let Some(bootstrap) = self.bootstrap {
+            campaign.bootstrap = bootstrap;
+        }
+        if let Some(calibration) = self.calibration {
+            campaign.calibration = calibration;
+        }
+        if !self.include_kernel.is_empty() {
+            campaign.kernels.include = self.include_kernel;
+        }
+        if !self.exclude_kernel.is_empty() {
+            campaign.kernels.exclude = self.exclude_kernel;
+        }
+        if !self.format.is_empty() {
+            campaign.formats = self.format;
+        }
+    }
 }
 
-fn stage3(cmds: Vec<&str>, rnd_cmd: &str, res_root: &str, times: usize, threshold: f64) {
-    log::info!("Stage 3 start");
-    for (idx, cmd) in cmds.iter().enumerate() {
-        log::info!("test idx: {}, cmd: `{}`", idx, cmd);
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
 
-        let trace_path = format!("{}/{}", &res_root, idx);
+    if std::env::var("RUST_LOG").is_err() {
+        env_logger::builder()
+            .filter_level(match cli.log {
+                0 => log::LevelFilter::Off,
+                1 => log::LevelFilter::Error,
+                2 => log::LevelFilter::Warn,
+                3 => log::LevelFilter::Info,
+                4 => log::LevelFilter::Debug,
+                5 => log::LevelFilter::Trace,
+                _ => log::LevelFilter::Info,
+            })
+            .init();
+    } else {
+        env_logger::init();
+    }
 
-        let report = leakage_test(&trace_path, &cmd, &rnd_cmd, times, threshold);
+    let mut campaign = match &cli.config {
+        Some(path) => Campaign::load(path)?,
+        None => Campaign::default(),
+    };
+    if cli.res_root.is_some() {
+        campaign.res_root = cli.res_root;
+    }
+    campaign.abort_on_bad_run |= cli.abort_on_bad_run;
+    let res_root = campaign.res_root();
 
-        let mut f = std::fs::File::create(&format!("{}/report.json", &trace_path)).unwrap();
+    match cli.command {
+        Command::Record { cmds, monitor } => {
+            cmds.apply(&mut campaign)?;
+            monitor.apply(&mut campaign);
 
-        log::info!("Dumping report to {}/report.json", &trace_path);
-        f.write_all(serde_json::to_string_pretty(&report).unwrap().as_bytes())
-            .unwrap();
-        log::info!("Report saved to {}/report.json", &trace_path);
+            stage::record(&campaign.cmds, &res_root, &campaign.monitor())?;
+        }
+        Command::Dedup => {
+            stage::dedup(&res_root, campaign.abort_on_bad_run)?;
+        }
+        Command::Test {
+            cmds,
+            test,
+            report,
+            monitor,
+        } => {
+            test.apply(&mut campaign);
+            report.apply(&mut campaign);
+            monitor.apply(&mut campaign);
+            let Some(threshold) = campaign.threshold() else {
+                return Ok(());
+            };
+            if campaign.rand_cmd.is_none() {
+                log::error!("rand cmd is not set, use `--rand-cmd` or `rand_cmd` of the config");
+                return Ok(());
+            }
+
+            let cmds = if cmds.is_empty() {
+                stage::selected(&res_root, &campaign.cmds)?
+            } else {
+                cmds.collect()?
+            };
+
+            stage::test(&cmds, &campaign, threshold, &res_root)?;
+        }
+        Command::Report(report) => {
+            report.apply(&mut campaign);
+            let Some(threshold) = campaign.threshold() else {
+                return Ok(());
+            };
+
+            stage::report(&campaign, threshold, &res_root)?;
+        }
+        Command::Run {
+            cmds,
+            test,
+            report,
+            monitor,
+        } => {
+            cmds.apply(&mut campaign)?;
+            test.apply(&mut campaign);
+            report.apply(&mut campaign);
+            monitor.apply(&mut campaign);
+            let Some(threshold) = campaign.threshold() else {
+                return Ok(());
+            };
+            if campaign.rand_cmd.is_none() {
+                log::error!("rand cmd is not set, use `--rand-cmd` or `rand_cmd` of the config");
+                return Ok(());
+            }
+            let monitor = campaign.monitor();
+
+            log::info!("Leakage test start");
+
+            let dedup = if campaign.cmds.len() > 1 {
+                stage::record(&campaign.cmds, &res_root, &monitor)?;
+                stage::dedup(&res_root, campaign.abort_on_bad_run)?
+            } else {
+                log::warn!("Only one command, skip stage 1 and 2");
+                stage::Dedup::keep_all(&campaign.cmds)
+            };
+
+            stage::test(&dedup.cmds, &campaign, threshold, &res_root)?;
+
+            stage::report(&campaign, threshold, &res_root)?;
+        }
     }
+
+    log::info!("Analyze finished");
+
+    Ok(())
 }
@@ -0,0 +1,297 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{self, BufReader, Write},
+};
+
+use analyzer::{
+    indexed_dirs, load_trace, record_traces, Analyzer, Error, Monitor, Report, Result, Schedule,
+    Trace,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::config::{Campaign, CAMPAIGN_FILE};
+
+/// Commands recorded by stage 1, indexed by their stage 1 directory
+pub const STAGE1_CMDS: &str = "stage1/cmds.json";
+/// Dedup decision of stage 2
+pub const STAGE2_DEDUP: &str = "stage2/dedup.json";
+/// Commands finished by stage 3
+pub const STAGE3_PROGRESS: &str = "stage3/progress.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Dedup {
+    /// commands kept for leakage test, indexed by their test directory
+    pub cmds: Vec<String>,
+    /// stage 1 index of every kept command
+    pub origin: Vec<usize>,
+    /// stage 1 index of a dropped command -> stage 1 index of the kept command with the same trace
+    pub same: BTreeMap<usize, usize>,
+    /// stage 1 index of commands dropped because their run failed
+    #[serde(default)]
+    pub failed: Vec<usize>,
+}
+
+impl Dedup {
+    /// Keep every command, used when stage 1 and 2 are skipped
+    pub fn keep_all(cmds: &[String]) -> Self {
+        Self {
+            cmds: cmds.to_vec(),
+            origin: (0..cmds.len()).collect(),
+            same: BTreeMap::new(),
+            failed: Vec::new(),
+        }
+    }
+}
+
+/// Stage 3 progress, used to resume a campaign at the right command
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Progress {
+    pub cmds: Vec<String>,
+    /// index of commands whose runs are all recorded
+    pub done: BTreeSet<usize>,
+}
+
+/// Stage 1: record one trace of every command to `res_root/stage1/<idx>`
+pub fn record(cmds: &[String], res_root: &str, monitor: &Monitor) -> io::Result<()> {
+    log::info!("Stage 1 start");
+    write_json(&format!("{res_root}/{STAGE1_CMDS}"), &cmds)?;
+
+    record_traces(cmds, &format!("{res_root}/stage1"), monitor)?;
+
+    Ok(())
+}
+
+/// Stage 2: drop commands whose stage 1 trace is the same as an earlier one
+/// A command whose stage 1 trace is bad is dropped unless `abort_on_bad_run` is set
+pub fn dedup(res_root: &str, abort_on_bad_run: bool) -> Result<Dedup> {
+    log::info!("Stage 2 start");
+    let cmds: Vec<String> = read_json(&format!("{res_root}/{STAGE1_CMDS}"))?;
+
+    let mut kept: Vec<(Trace, usize)> = Vec::new();
+    let mut dedup = Dedup::default();
+
+    for (idx, cmd) in cmds.iter().enumerate() {
+        let trace = match load_trace(&format!("{res_root}/stage1/{idx}")) {
+            Ok(Some(trace)) => trace,
+            Ok(None) => {
+                log::warn!("Stage 1 run of {idx} failed, drop `{cmd}`");
+                dedup.failed.push(idx);
+                continue;
+            }
+            Err(e) if !abort_on_bad_run => {
+                log::warn!("Stage 1 trace of {idx} is bad ({e}), drop `{cmd}`");
+                dedup.failed.push(idx);
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Some((_, same_idx)) = kept.iter().find(|(t, _)| t.same(&trace)) {
+            log::info!("Find same trace, {idx} and {same_idx}");
+            dedup.same.insert(idx, *same_idx);
+        } else {
+            dedup.cmds.push(cmd.clone());
+            dedup.origin.push(idx);
+            kept.push((trace, idx));
+        }
+    }
+
+    write_json(&format!("{res_root}/{STAGE2_DEDUP}"), &dedup)?;
+
+    Ok(dedup)
+}
+
+/// Commands to test: the ones selected by stage 2 out of `cmds`, `cmds` themselves if stage 1
+/// recorded other commands. Empty `cmds` select out of every stage 1 command, all of them if
+/// there is no stage 2 result
+pub fn selected(res_root: &str, cmds: &[String]) -> io::Result<Vec<String>> {
+    if !cmds.is_empty() {
+        let recorded = read_json::<Vec<String>>(&format!("{res_root}/{STAGE1_CMDS}"));
+        if !recorded.is_ok_and(|recorded| recorded == cmds) {
+            log::info!("Commands not recorded by stage 1, test every one");
+            return Ok(cmds.to_vec());
+        }
+    }
+
+    match read_json::<Dedup>(&format!("{res_root}/{STAGE2_DEDUP}")) {
+        Ok(dedup) => Ok(dedup.cmds),
+        Err(e) => {
+            log::warn!("No stage 2 result ({e}), use stage 1 commands");
+            read_json(&format!("{res_root}/{STAGE1_CMDS}"))
+        }
+    }
+}
+
+/// Stage 3: record fixed and random input traces of every command to `res_root/<idx>`,
+/// the campaign recording them is saved next to them
+pub fn test(cmds: &[String], campaign: &Campaign, threshold: f64, res_root: &str) -> Result<()> {
+    log::info!("Stage 3 start");
+    let monitor = campaign.monitor();
+    let progress_path = format!("{res_root}/{STAGE3_PROGRESS}");
+    let mut progress = match read_json::<Progress>(&progress_path) {
+        Ok(progress) if monitor.resume && progress.cmds == cmds => progress,
+        Ok(_) if monitor.resume => {
+            log::warn!("Commands changed since the last campaign, start from the first one");
+            Progress::default()
+        }
+        _ => Progress::default(),
+    };
+    progress.cmds = cmds.to_vec();
+
+    for (idx, cmd) in cmds.iter().enumerate() {
+        if progress.done.contains(&idx) {
+            log::info!("skip finished idx: {}, cmd: `{}`", idx, cmd);
+            continue;
+        }
+        log::info!("test idx: {}, cmd: `{}`", idx, cmd);
+
+        let mut analyzer = Analyzer {
+            fix_cmd: cmd.to_owned(),
+            rnd_cmd: campaign.rand_cmd.clone().unwrap_or_default(),
+            fix_times: campaign.fix_times(),
+            rnd_times: campaign.rnd_times(),
+            schedule: campaign.schedule,
+            seed: campaign.seed,
+            threshold,
+            filter: campaign.kernels.clone(),
+            granularity: campaign.granularity,
+            coalescing: campaign.coalescing,
+            correction: campaign.correction,
+            method: campaign.method,
+            permutation: campaign.permutation(),
+            calibration: campaign.calibration,
+            calibration_times: campaign.calibration_times,
+            abort_on_bad_run: campaign.abort_on_bad_run,
+
+            trace_path: format!("{}/{}", res_root, idx),
+            monitor: monitor.clone(),
+            ..Default::default()
+        };
+
+        if let Some(adaptive) = campaign.adaptive() {
+            let sampling = match analyzer.record_adaptive(&adaptive) {
+                Ok(sampling) => sampling,
+                Err(e) => {
+                    skip_or_abort(campaign, idx, e)?;
+                    continue;
+                }
+            };
+            log::info!(
+                "{} fix runs, {} rnd runs needed, {} sites undecided",
+                sampling.fix_runs,
+                sampling.rnd_runs,
+                sampling.undecided.len()
+            );
+        } else {
+            analyzer.record()?;
+        }
+
+        let recorded = Campaign {
+            cmds: vec![cmd.to_owned()],
+            seed: Schedule::load(&analyzer.trace_path)
+                .ok()
+                .and_then(|schedule| schedule.seed)
+                .or(campaign.seed),
+            ..campaign.clone()
+        };
+        write_json(
+            &format!("{}/{CAMPAIGN_FILE}", analyzer.trace_path),
+            &recorded,
+        )?;
+
+        progress.done.insert(idx);
+        write_json(&progress_path, &progress)?;
+    }
+
+    Ok(())
+}
+
+/// Analyze every command directory recorded under `res_root` and write the report in every
+/// format of the campaign, the campaign is saved next to it with the settings its runs were
+/// recorded with
+pub fn report(campaign: &Campaign, threshold: f64, res_root: &str) -> Result<()> {
+    log::info!("Report start");
+    for idx in indexed_dirs(res_root) {
+        let trace_path = format!("{}/{}", res_root, idx);
+        log::info!("analyze idx: {}, path: `{}`", idx, trace_path);
+
+        let campaign = match Campaign::load(&format!("{trace_path}/{CAMPAIGN_FILE}")) {
+            Ok(recorded) => {
+                let (reported, conflicts) = campaign.reported(&recorded)?;
+                for key in conflicts {
+                    log::warn!("`{key}` differs from the recorded campaign of {idx}, ignored");
+                }
+                reported
+            }
+            Err(e) => {
+                log::warn!("No recorded campaign of {idx} ({e})");
+                campaign.clone()
+            }
+        };
+        let campaign = &campaign;
+
+        let mut analyzer = Analyzer {
+            threshold,
+            trace_path: trace_path.clone(),
+            filter: campaign.kernels.clone(),
+            granularity: campaign.granularity,
+            coalescing: campaign.coalescing,
+            correction: campaign.correction,
+            method: campaign.method,
+            permutation: campaign.permutation(),
+            calibration: campaign.calibration,
+            abort_on_bad_run: campaign.abort_on_bad_run,
+            ..Default::default()
+        };
+
+        let report = match analyzer.analyze() {
+            Ok(report) => report,
+            Err(e) => {
+                skip_or_abort(campaign, idx, e)?;
+                continue;
+            }
+        };
+
+        dump_report(&trace_path, &report, campaign)?;
+    }
+
+    Ok(())
+}
+
+/// A command failing analysis is skipped unless `abort_on_bad_run` is set
+fn skip_or_abort(campaign: &Campaign, idx: usize, err: Error) -> Result<()> {
+    if campaign.abort_on_bad_run {
+        return Err(err);
+    }
+
+    log::error!("skip command {idx}: {err}");
+    Ok(())
+}
+
+fn dump_report(trace_path: &str, report: &Report, campaign: &Campaign) -> Result<()> {
+    for format in campaign.formats.iter() {
+        let path = format!("{}/report.{}", trace_path, format.ext());
+        log::info!("Dumping report to {}", path);
+        File::create(&path)?.write_all(report.render(*format)?.as_bytes())?;
+        log::info!("Report saved to {}", path);
+    }
+
+    Ok(write_json(
+        &format!("{trace_path}/{CAMPAIGN_FILE}"),
+        campaign,
+    )?)
+}
+
+fn write_json(path: &str, value: &impl Serialize) -> io::Result<()> {
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    File::create(path)?.write_all(serde_json::to_string_pretty(value)?.as_bytes())
+}
+
+fn read_json<T: DeserializeOwned>(path: &str) -> io::Result<T> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
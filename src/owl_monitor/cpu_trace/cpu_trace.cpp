@@ -96,6 +96,30 @@ void DumpJson(json j) {
     owl::json_to_file(j, filename);
 }
 
+/* host call stack at `ctxt` as {addr, func, file, offset = line} frames */
+json Backtrace(const CONTEXT *ctxt) {
+  void *frames[32];
+  int depth = PIN_Backtrace(ctxt, frames, 32);
+
+  json bt = json::array();
+  PIN_LockClient();
+  for (int i = 0; i < depth; i++) {
+    ADDRINT addr = reinterpret_cast<ADDRINT>(frames[i]);
+    INT32 line = 0;
+    string file;
+    PIN_GetSourceLocation(addr, nullptr, &line, &file);
+
+    json frame = json::object();
+    frame["addr"] = addr;
+    frame["func"] = RTN_FindNameByAddress(addr);
+    frame["file"] = file;
+    frame["offset"] = line;
+    bt.push_back(frame);
+  }
+  PIN_UnlockClient();
+  return bt;
+}
+
 typedef struct {
   uint64_t addr;
   uint64_t size;
@@ -110,6 +134,7 @@ struct AllocInfo {
   ADDRINT host_ptr;
   ADDRINT cur_ptr;
   ADDRINT cur_size;
+  json bt;
 
   json to_json_lite() const {
     json j = json::object();
@@ -122,7 +147,7 @@ struct AllocInfo {
   json to_json() const {
     json result = json::object();
     result["type"] = to_str(JsonType::alloc);
-    result["data"] = to_json_lite();
+    result["data"] = json::array({to_json_lite()});
 
     return result;
   }
@@ -130,10 +155,15 @@ struct AllocInfo {
   void dump() const { DumpJson(this->to_json()); }
 };
 
-VOID DeviceAllocBefore(CHAR *name, ADDRINT ptr, ADDRINT size, AllocInfo *ai) {
+/* every device allocation of the run, dumped to OWL_TRACE at exit */
+vector<AllocInfo> alloc_records;
+
+VOID DeviceAllocBefore(CHAR *name, ADDRINT ptr, ADDRINT size, AllocInfo *ai,
+                       const CONTEXT *ctxt) {
   ai->name = name;
   ai->cur_ptr = ptr;
   ai->cur_size = size;
+  ai->bt = Backtrace(ctxt);
 }
 
 VOID DeviceAllocAfter(AllocInfo *ai) {
@@ -147,6 +177,7 @@ VOID DeviceAllocAfter(AllocInfo *ai) {
   dev_mem.push_back(
       new AllocBasicInfo{.addr = ai->cur_ptr, .size = ai->cur_size});
   sharedMemory.write(dev_mem);
+  alloc_records.push_back(*ai);
 }
 
 /**
@@ -158,14 +189,16 @@ map<ADDRINT, ADDRINT> host_2_dev;
 vector<AllocInfo *> host_alloc_mem;
 
 VOID HostAllocBefore(CHAR *name, ADDRINT host_ptr, ADDRINT size,
-                     AllocInfo *ai) {
+                     AllocInfo *ai, const CONTEXT *ctxt) {
   ai->name = name;
   ai->host_ptr = host_ptr;
   ai->cur_size = size;
+  ai->bt = Backtrace(ctxt);
 }
 
 VOID HostAllocAfter(AllocInfo *ai) {
 
+  ai = new AllocInfo(*ai);
   host_alloc_mem.push_back(ai);
   DEBUG(2)
   WARNF("%s Allocation in host address: 0x%lx, size: 0x%lx", ai->name,
@@ -209,6 +242,7 @@ VOID HostGetDevicePointerAfter() {
       new AllocBasicInfo{.addr = ai->cur_ptr, .size = ai->cur_size});
 
   sharedMemory.write(dev_mem);
+  alloc_records.push_back(*ai);
   ai = nullptr;
 }
 
@@ -252,13 +286,13 @@ VOID TraceMemoryPool(IMG img, VOID *v) {
       RTN_InsertCall(devMallocRtn, LEVEL_VM::IPOINT_BEFORE,
                      (AFUNPTR)DeviceAllocBefore, IARG_ADDRINT, fname,
                      IARG_FUNCARG_ENTRYPOINT_VALUE, 0,
-                     IARG_FUNCARG_ENTRYPOINT_VALUE, 1, IARG_PTR, ai, IARG_END);
+                     IARG_FUNCARG_ENTRYPOINT_VALUE, 1, IARG_PTR, ai,
+                     IARG_CONST_CONTEXT, IARG_END);
 
       RTN_InsertCall(devMallocRtn, LEVEL_VM::IPOINT_AFTER,
                      (AFUNPTR)DeviceAllocAfter, IARG_PTR, ai, IARG_END);
 
       RTN_Close(devMallocRtn);
-      free(ai);
     }
   }
 
@@ -274,13 +308,13 @@ VOID TraceMemoryPool(IMG img, VOID *v) {
       RTN_InsertCall(hostMallocRtn, LEVEL_VM::IPOINT_BEFORE,
                      (AFUNPTR)HostAllocBefore, IARG_ADDRINT, fname,
                      IARG_FUNCARG_ENTRYPOINT_VALUE, 0,
-                     IARG_FUNCARG_ENTRYPOINT_VALUE, 1, IARG_PTR, ai, IARG_END);
+                     IARG_FUNCARG_ENTRYPOINT_VALUE, 1, IARG_PTR, ai,
+                     IARG_CONST_CONTEXT, IARG_END);
 
       RTN_InsertCall(hostMallocRtn, LEVEL_VM::IPOINT_AFTER,
                      (AFUNPTR)HostAllocAfter, IARG_PTR, ai, IARG_END);
 
       RTN_Close(hostMallocRtn);
-      free(ai);
     }
   }
 
@@ -324,7 +358,37 @@ INT32 Usage() {
   return -1;
 }
 
+/* write alloc.json and context.json to OWL_TRACE, contexts are keyed by the
+   allocated address */
+VOID DumpAllocs() {
+  char *dirname = getenv(OWL_TRACE);
+  if (!dirname || !create_dir(dirname))
+    return;
+
+  json allocs = json::array();
+  json contexts = json::array();
+  for (const auto &a : alloc_records) {
+    allocs.push_back(a.to_json_lite());
+
+    json context = json::object();
+    context["addr"] = a.cur_ptr;
+    context["bt"] = a.bt;
+    contexts.push_back(context);
+  }
+
+  json alloc = json::object();
+  alloc["type"] = to_str(JsonType::alloc);
+  alloc["data"] = allocs;
+  owl::json_to_file(alloc, (string(dirname) + "/alloc.json").c_str());
+
+  json context = json::object();
+  context["type"] = to_str(JsonType::context);
+  context["data"] = contexts;
+  owl::json_to_file(context, (string(dirname) + "/context.json").c_str());
+}
+
 VOID Fini(INT32 code, VOID *v) {
+  DumpAllocs();
 
   sharedMemory.detach();
   sharedMemory.remove();
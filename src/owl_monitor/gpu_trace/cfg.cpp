@@ -94,7 +94,8 @@ void DCFGBuilder::record_bb_access(Dim cta_id, WarpID warp_id, NodeID node_id) {
 }
 
 void DCFGBuilder::record_mem_access(Dim cta_id, WarpID warp_id, NodeID node_id,
-                                    Addr offset, MemAccess access) {
+                                    Addr offset, MemAccess access, Type type,
+                                    const WarpAccess &warp_access) {
   
   
   WarpKey warp_key = make_pair(cta_id, warp_id);
@@ -119,10 +120,15 @@ void DCFGBuilder::record_mem_access(Dim cta_id, WarpID warp_id, NodeID node_id,
 
   
 
-  dcfg.nodes[node_id]->record_mem_access(warps[warp_key]->pos, offset, access);
+  dcfg.nodes[node_id]->record_mem_access(warps[warp_key]->pos, offset, access,
+                                         type, warp_access);
 }
 
-void Node::record_mem_access(Pos pos, Addr offset, MemAccess ma) {
+void Node::record_mem_access(Pos pos, Addr offset, MemAccess ma, Type type,
+                             const WarpAccess &warp) {
+  // the warp access is kept whole, bank conflicts and transactions are per warp access
+  warpAccessMap[offset][pos][type][warp] += 1;
+
   
   auto itOffset = memAccessMap.find(offset);
   if (itOffset != memAccessMap.end()) {
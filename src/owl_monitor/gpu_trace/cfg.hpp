@@ -39,6 +39,12 @@ using PosMemAccess = map<Pos, shared_ptr<MemAccess>>;
 
 using InstrMemAccess =  map<Addr, shared_ptr<PosMemAccess>>;
 
+// addresses of the active threads of one warp access, sorted and distinct
+using WarpAccess = vector<Addr>;
+// every distinct warp access of a position and how many times it happened
+using WarpAccesses = map<WarpAccess, Count>;
+using InstrWarpAccess = map<Addr, map<Pos, map<Type, WarpAccesses>>>;
+
 typedef struct {
   NodeID from;
   NodeID to;
@@ -51,9 +57,11 @@ typedef struct {
   map<ControlFlow, Count> ctrlFlowMap;
 
   InstrMemAccess memAccessMap;
+  InstrWarpAccess warpAccessMap;
 
   void Node(int _id = 0xffffffff){id = _id;}
-  void record_mem_access(Pos pos, Addr offset, MemAccess ma);
+  void record_mem_access(Pos pos, Addr offset, MemAccess ma, Type type,
+                         const WarpAccess &warp);
 } Node;
 
 
@@ -108,7 +116,8 @@ public:
   }
 
   void record_bb_access(Dim cta, WarpID warp, NodeID node);
-  void record_mem_access(Dim cta, WarpID warp, NodeID node, Addr offset, MemAccess access);
+  void record_mem_access(Dim cta, WarpID warp, NodeID node, Addr offset,
+                         MemAccess access, Type type, const WarpAccess &warp_access);
 
 
   DCFG dcfg;
@@ -72,9 +72,18 @@ json to_json(vector<KernelTrace> kernel_traces){
                             });
                         }
 
+                        json j_warps = json::array();
+                        for (auto w: node.second->warpAccessMap[offset][pos][ma.first]) {
+                            j_warps.push_back({
+                                {"addr", w.first},
+                                {"count", w.second}
+                            });
+                        }
+
                         j_access.push_back({
                             {"type", ma.first},
                             {"memory", j_ma},
+                            {"warps", j_warps},
                         });
                     }
                     j_mamap.push_back({
@@ -414,6 +414,7 @@ void deserialize_trace(u8 *buffer, u32 len) {
       mem_record_t *r = (mem_record_t *)cur_buffer;
 
       auto bma = make_shared<BasicMemAccess>();
+      WarpAccess warp;
 
       for (int i = 0; i < 32; i++) {
         if (!((r->info.active >> i) % 2))
@@ -422,14 +423,18 @@ void deserialize_trace(u8 *buffer, u32 len) {
         (*bma)[addr] =
             ((*bma).find(addr) != (*bma).end()) ? (*bma)[addr] + 1 : 1;
       }
+      for (auto m : *bma)
+        warp.push_back(m.first);
 
+      auto mem_type = InstrType::MemorySpaceStr[r->type];
       MemAccess ma;
-      ma[InstrType::MemorySpaceStr[r->type]] = bma;
+      ma[mem_type] = bma;
 
       try {
 
         kernel_info[r->info.kernelid]->builder.record_mem_access(
-            r->info.ctaid, r->info.warpid, r->info.bbid, r->offset, ma);
+            r->info.ctaid, r->info.warpid, r->info.bbid, r->offset, ma,
+            mem_type, warp);
       } catch (const char *&e) {
         auto funcID = kernel_info[r->info.kernelid]->funcID;
         for (auto f : f_id) {